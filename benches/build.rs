@@ -0,0 +1,126 @@
+//! Builds a synthetic corpus with one thread and then with every core, checking
+//! that both write the same site, run with `cargo bench`
+//!
+//! The timings are informational only. Set `NAKSSG_BENCH_POSTS` for a corpus other
+//! than the default 400 posts, a few thousand make for steadier numbers.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+fn posts() -> usize {
+    std::env::var("NAKSSG_BENCH_POSTS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(400)
+}
+
+fn write_corpus(dir: &Path, posts: usize) {
+    let pages = dir.join("pages");
+    std::fs::create_dir_all(pages.join("static")).unwrap();
+    for i in 0..posts {
+        let file = std::fs::File::create(pages.join(format!("post-{i}.md"))).unwrap();
+        let mut writer = std::io::BufWriter::new(file);
+        writeln!(writer, "---").unwrap();
+        writeln!(writer, "title: Post number {i}").unwrap();
+        writeln!(
+            writer,
+            "timestamp: 2024-10-23T20:{:02}:{:02}Z",
+            i / 60 % 60,
+            i % 60
+        )
+        .unwrap();
+        writeln!(writer, "tags: [tag-{}, tag-{}]", i % 17, i % 5).unwrap();
+        writeln!(writer, "---").unwrap();
+        for section in 0..20 {
+            writeln!(writer, "## Section {section}\n").unwrap();
+            writeln!(
+                writer,
+                "Lorem ipsum *dolor* sit amet, `consectetur` adipiscing elit, sed do **eiusmod** tempor.\n"
+            )
+            .unwrap();
+            writeln!(writer, "| a | b |\n|---|---|\n| {i} | {section} |\n").unwrap();
+            writeln!(
+                writer,
+                "- one\n- two\n- [link](/post-{})\n",
+                (i + 1) % posts
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Runs a clean build of `dir` into `output`, returning how long it took
+fn build(dir: &Path, jobs: Option<usize>, output: &str) -> Duration {
+    let _ = std::fs::remove_dir_all(dir.join(output));
+    let _ = std::fs::remove_dir_all(dir.join(".nakssg-cache"));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_nakssg"));
+    command.current_dir(dir);
+    if let Some(jobs) = jobs {
+        command.args(["--jobs", &jobs.to_string()]);
+    }
+    command.args(["build", "--output-dir", output]);
+    let start = Instant::now();
+    let output = command.output().unwrap();
+    let elapsed = start.elapsed();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    elapsed
+}
+
+/// Every file under `dir` by its path relative to it, with its contents
+fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        for entry in std::fs::read_dir(next).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let contents = std::fs::read(&path).unwrap();
+                files.insert(path.strip_prefix(dir).unwrap().to_path_buf(), contents);
+            }
+        }
+    }
+    files
+}
+
+fn main() {
+    let posts = posts();
+    let dir = std::env::temp_dir().join(format!("nakssg-bench-{}", std::process::id()));
+    write_corpus(&dir, posts);
+
+    let sequential = build(&dir, Some(1), "dist-sequential");
+    let parallel = build(&dir, None, "dist-parallel");
+    let sequential_files = read_tree(&dir.join("dist-sequential"));
+    let parallel_files = read_tree(&dir.join("dist-parallel"));
+    assert!(
+        sequential_files.keys().eq(parallel_files.keys()),
+        "the builds wrote different files"
+    );
+    for (path, contents) in &sequential_files {
+        assert!(
+            parallel_files[path] == *contents,
+            "the builds wrote different {}",
+            path.display()
+        );
+    }
+    let cores = std::thread::available_parallelism().map_or(1, |x| x.get());
+    println!("{posts} posts, {} files", sequential_files.len());
+    println!("  --jobs 1: {sequential:?}");
+    println!("  --jobs {cores}: {parallel:?}");
+    println!(
+        "  speedup: {:.2}x",
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
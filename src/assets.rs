@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cache, config, error::Error, highlight};
+
+/// Stylesheet of the built in layout
+pub const STYLESHEET: &str = "style.css";
+/// Script of the built in layout
+pub const SCRIPT: &str = "script.js";
+
+const BASE_CSS: &str = include_str!("assets/base.css");
+const BASE_JS: &str = include_str!("assets/base.js");
+
+/// Bundles of stylesheets or scripts, set under `[assets]` in the site config
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetOptions {
+    /// Files in the static directory to put together under each name, in order,
+    /// e.g. `"site.css" = ["reset.css", "theme.css"]`
+    pub bundles: BTreeMap<String, Vec<String>>,
+}
+
+/// A minified stylesheet or script, written under a name with a hash of its contents
+/// in it so browsers can hold on to it for good
+pub struct Asset {
+    /// e.g. `assets/style.3f9a1c5e07.css`
+    pub output: String,
+    pub contents: String,
+}
+
+/// Everything the built in layout needs, the bundles from the site config and every
+/// stylesheet and script among `files`, those in `static_dir`, by the name they are
+/// looked up with
+///
+/// The built in assets and bundles go by their own name, e.g. `style.css`, and the files
+/// in `static_dir` by where they are copied to, e.g. `static/extra.css`.
+pub fn collect(
+    static_dir: &Path,
+    files: &[(PathBuf, String)],
+) -> (BTreeMap<String, Asset>, Vec<Error>) {
+    let mut assets = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut add = |name: String, output_dir: &str, source: Result<String, Error>| {
+        let contents = match source.and_then(|x| minify(&name, &x)) {
+            Ok(contents) => contents,
+            Err(err) => return errors.push(err),
+        };
+        let output = fingerprint(output_dir, &name, &contents);
+        assets.insert(name, Asset { output, contents });
+    };
+
+    add(STYLESHEET.to_string(), "assets", Ok(BASE_CSS.to_string()));
+    add(SCRIPT.to_string(), "assets", Ok(BASE_JS.to_string()));
+    add(
+        highlight::STYLESHEET.to_string(),
+        "assets",
+        highlight::theme_css(&config::get().highlight_theme),
+    );
+    for (name, sources) in &config::get().assets.bundles {
+        let bundle = sources
+            .iter()
+            .map(|x| {
+                let path = static_dir.join(x);
+                std::fs::read_to_string(&path).map_err(Error::io("read", path))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            // The semicolon keeps one script from running into the next
+            .map(|x| x.join(if name.ends_with(".js") { ";\n" } else { "\n" }));
+        add(name.clone(), "assets", bundle);
+    }
+    let files = files
+        .iter()
+        .filter(|(path, _)| path.extension().is_some_and(|x| x == "css" || x == "js"));
+    for (path, name) in files {
+        let source = std::fs::read_to_string(path).map_err(Error::io("read", path));
+        let (dir, _) = name.rsplit_once('/').unwrap_or(("", name));
+        let dir = dir.to_string();
+        add(name.clone(), &dir, source);
+    }
+    (assets, errors)
+}
+
+fn minify(name: &str, source: &str) -> Result<String, Error> {
+    let minified = if name.ends_with(".css") {
+        minifier::css::minify(source).map(|x| x.to_string())
+    } else if name.ends_with(".js") {
+        minifier::js::minify(source).map(|x| x.to_string())
+    } else {
+        Ok(source.to_string())
+    };
+    minified.map_err(|reason| Error::Asset {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Where `name` is written in `dir`, with a hash of `contents` before its extension
+fn fingerprint(dir: &str, name: &str, contents: &str) -> String {
+    let hash = cache::hash([contents]);
+    let file_name = name.rsplit_once('/').map_or(name, |(_, x)| x);
+    let file_name = match file_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}.{}.{extension}", &hash[..10]),
+        None => format!("{file_name}.{}", &hash[..10]),
+    };
+    if dir.is_empty() {
+        file_name
+    } else {
+        format!("{dir}/{file_name}")
+    }
+}
+
+static URLS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Makes the URLs of `assets` available through [`url`] for the build that is running
+pub fn set(assets: &BTreeMap<String, Asset>) {
+    *URLS.write().unwrap() = assets
+        .iter()
+        .map(|(name, asset)| (name.clone(), format!("/{}", asset.output)))
+        .collect();
+}
+
+/// The fingerprinted URL of the asset `name`, e.g. `style.css` or `static/extra.css`,
+/// or `/name` as is if there is no such asset
+pub fn url(name: &str) -> String {
+    URLS.read()
+        .unwrap()
+        .get(name.trim_start_matches('/'))
+        .cloned()
+        .unwrap_or_else(|| format!("/{}", name.trim_start_matches('/')))
+}
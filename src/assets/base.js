@@ -0,0 +1,5 @@
+document.querySelectorAll('time').forEach($e => {
+  const date = new Date($e.dateTime);
+  // output the localized date and time
+  $e.innerHTML = date.toLocaleString();
+});
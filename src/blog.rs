@@ -1,66 +1,70 @@
 use std::collections::HashMap;
 
 use trowel::util::{Doctype, HeadDefault};
-use trowel::{html::Attribute, ToHtml};
+use trowel::{
+    html::{self, Attribute, Error},
+    ToHtml,
+};
 use trowel::{trowel_html, HtmlWriter};
 
+use crate::{assets, config, highlight, permalink, xref::Target, Page};
+
 #[allow(non_snake_case)]
-pub fn BlogPageBase(attrs: Vec<Attribute>, children: impl Fn(&mut dyn HtmlWriter)) -> impl ToHtml {
+pub fn BlogPageBase(
+    attrs: Vec<Attribute>,
+    children: impl Fn(&mut dyn HtmlWriter) -> html::Result,
+) -> html::Result<impl ToHtml> {
     let mut attrs: HashMap<_, _> = attrs.into_iter().collect();
-    let title = attrs.remove("title").expect("No title").unwrap();
+    let title = attrs
+        .remove("title")
+        .flatten()
+        .ok_or_else(|| Error::new("no title"))?;
     let timestamp = attrs.remove("timestamp").flatten();
+    let prev = attrs.remove("prev").flatten();
+    let next = attrs.remove("next").flatten();
+    // e.g. `noindex`
+    let robots = attrs.remove("robots").flatten();
+    let config = config::get();
 
-    trowel_html! { move
+    Ok(trowel_html! { move
         <!Doctype,
-        html {
+        html(lang: {Some(&config.language)}) {
             head {
-                <!HeadDefault,
+                <!HeadDefault(no_stylesheets),
+                {
+                    config.stylesheets.iter().map(|x| trowel_html! { move
+                        <link(rel: "stylesheet", href: {Some(x)}, type: "text/css")
+                    }).collect::<Vec<_>>()
+                },
+                <link(rel: "stylesheet", href: {Some(assets::url(assets::STYLESHEET))}, type: "text/css"),
+                <link(rel: "stylesheet", href: {Some(assets::url(highlight::STYLESHEET))}, type: "text/css"),
                 title {
                     {title.as_str()}
                 },
-                style {
-                    r#"
-nav {
-  display: flex;
-  justify-content: space-between;
-}
-
-aside {
-  width: 40%;
-  padding-left: 0.5rem;
-  margin-left: 0.5rem;
-  float: right;
-  box-shadow: inset 5px 0 5px -5px #49002d;
-  font-style: italic;
-  color: #49002d;
-}
-
-@media (min-width: calc(38em * 2.2)) {
-  aside {
-    float: none; /* Disable floating */
-    position: absolute; /* Take it out of the document flow */
-    right: -40%;
-    width: calc(38em * 0.4);
-  }
-  body {
-    position: relative;
-  }
-}
-                    "#
-                },
+                {robots.as_ref().map(|x| trowel_html! { move
+                    <meta(name: "robots", content: {Some(x)})
+                })},
+                {prev.as_ref().map(|x| trowel_html! { move
+                    <link(rel: "prev", href: {Some(x)})
+                })},
+                {next.as_ref().map(|x| trowel_html! { move
+                    <link(rel: "next", href: {Some(x)})
+                })},
             },
             body {
                 header {
                     nav {
                         div {
-                            "TODO"
+                            a(href: "/") {
+                                {config.title.as_str()}
+                            }
                         },
                         div {
                             {
-                                vec![("/", "Home"), ("/about", "Bbout")].into_iter().map(|x|
+                                config.menu.iter().map(|x|
                                     trowel_html!{ move
-                                        a(href: {Some(x.0.to_string())}) {
-                                            {x.1}
+                                        a(href: {Some(&x.url)}) {
+                                            {x.name.as_str()}
                                         }
                                     }
                                 ).collect::<Vec<_>>()
@@ -80,16 +84,133 @@ aside {
 
                     {children}
                 },
-                script {
-                    r#"
-document.querySelectorAll('time').forEach($e => {
-  const date = new Date($e.dateTime);
-  // output the localized date and time
-  $e.innerHTML = date.toLocaleString();
-});
-                    "#
-                }
+                script(src: {Some(assets::url(assets::SCRIPT))}, defer) {}
             },
         }
+    })
+}
+
+pub fn page_list<'a>(
+    pages: impl IntoIterator<Item = &'a Page>,
+) -> impl Fn(&mut dyn HtmlWriter) -> html::Result + 'a {
+    let pages = pages.into_iter().collect::<Vec<_>>();
+    move |writer: &mut dyn HtmlWriter| {
+        (trowel_html! {
+            ul(style: "list-style-type:none;") {
+                {
+                    pages.iter().map(|page| {
+                        trowel_html!(
+                            li {
+                                a(href: {Some(&page.url)}) {
+                                    {page.title.as_str()},
+                                    sub {
+                                        !{let timestamp = page.timestamp.to_rfc2822();},
+                                        time(datetime: {Some(&timestamp)}) {
+                                            {timestamp}
+                                        }
+                                    }
+                                }
+                            }
+                        )
+                    }).collect::<Vec<_>>()
+                }
+            }
+        })
+        .to_html(writer)
     }
 }
+
+/// Links to the pages that link to a page
+pub fn backlinks(targets: &[Target]) -> impl Fn(&mut dyn HtmlWriter) -> html::Result + '_ {
+    move |writer: &mut dyn HtmlWriter| {
+        if targets.is_empty() {
+            return Ok(());
+        }
+        (trowel_html! {
+            nav(class: "backlinks") {
+                h2 {
+                    "Linked from"
+                },
+                ul {
+                    {
+                        targets.iter().map(|target| {
+                            trowel_html! { move
+                                li {
+                                    a(href: {Some(&target.url)}) {
+                                        {target.title.as_str()}
+                                    }
+                                }
+                            }
+                        }).collect::<Vec<_>>()
+                    }
+                }
+            }
+        })
+        .to_html(writer)
+    }
+}
+
+/// How many pages the index of `posts` posts is split into
+pub fn index_page_count(posts: usize) -> usize {
+    let paginate = config::get().paginate;
+    if paginate == 0 || posts == 0 {
+        1
+    } else {
+        posts.div_ceil(paginate)
+    }
+}
+
+/// URL of the `n`th page of the paginated index, counting from 1
+pub fn pagination_url(n: usize) -> String {
+    if n <= 1 {
+        "/".to_string()
+    } else {
+        permalink::fill(&config::get().pagination_permalink, |name| {
+            (name == "page").then(|| n.to_string())
+        })
+        .expect("checked when the config was loaded")
+    }
+}
+
+/// Links to the neighbours of the `current` out of `total` index pages
+#[allow(non_snake_case)]
+pub fn Pagination(
+    attrs: Vec<Attribute>,
+    _children: impl Fn(&mut dyn HtmlWriter) -> html::Result,
+) -> html::Result<impl ToHtml> {
+    let mut attrs: HashMap<_, _> = attrs.into_iter().collect();
+    let current: usize = attrs
+        .remove("current")
+        .flatten()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| Error::new("no current page"))?;
+    let total: usize = attrs
+        .remove("total")
+        .flatten()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| Error::new("no page count"))?;
+
+    Ok(trowel_html! { move
+        {(total > 1).then_some(trowel_html! {
+            nav(class: "pagination") {
+                span {
+                    {(current > 1).then_some(trowel_html! {
+                        a(href: {Some(pagination_url(current - 1))}, rel: "prev") {
+                            "&laquo; Newer"
+                        }
+                    })}
+                },
+                span {
+                    {format!("Page {current} of {total}")}
+                },
+                span {
+                    {(current < total).then_some(trowel_html! {
+                        a(href: {Some(pagination_url(current + 1))}, rel: "next") {
+                            "Older &raquo;"
+                        }
+                    })}
+                }
+            }
+        })}
+    })
+}
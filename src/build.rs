@@ -0,0 +1,541 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use minijinja::{context, Value};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use trowel::{trowel_html, Raw};
+
+use crate::{
+    assets,
+    blog::{backlinks, index_page_count, page_list, pagination_url, BlogPageBase, Pagination},
+    cache::{self, Cache},
+    config,
+    error::Error,
+    feed, files,
+    images::Images,
+    linkcheck, permalink, sitemap,
+    taxonomy::{Taxonomy, Term},
+    template::{HtmlStyle, PageContext, Templates},
+    toc, xref, Page,
+};
+
+/// Bump whenever the built in layout changes, so cached pages are rendered again
+const TEMPLATE_VERSION: &str = "8";
+
+#[derive(clap::Args, Clone)]
+pub struct BuildOptions {
+    /// Include posts marked as drafts
+    #[arg(long)]
+    pub drafts: bool,
+    /// Include posts with a publish_date in the future
+    #[arg(long)]
+    pub future: bool,
+    /// Fail the build on broken links instead of only warning about them
+    #[arg(long)]
+    pub strict: bool,
+    /// Build everything that can be built instead of stopping after the first
+    /// step that had errors
+    #[arg(short, long)]
+    pub keep_going: bool,
+    /// Minify the HTML of every page
+    #[arg(long)]
+    pub minify: bool,
+    /// Indent the HTML of every page one element per line, to debug layouts with
+    #[arg(long, conflicts_with = "minify")]
+    pub pretty: bool,
+}
+
+impl BuildOptions {
+    fn html_style(&self) -> HtmlStyle {
+        if self.minify {
+            HtmlStyle::Minified
+        } else if self.pretty {
+            HtmlStyle::Pretty
+        } else {
+            HtmlStyle::AsIs
+        }
+    }
+}
+
+/// Errors collected over a build, so they can all be reported at once
+struct Errors {
+    errors: Vec<Error>,
+    keep_going: bool,
+}
+
+impl Errors {
+    /// Keeps the error of `result`, if any
+    fn collect<T>(&mut self, result: Result<T, Error>) -> Option<T> {
+        match result {
+            Ok(x) => Some(x),
+            Err(err) => {
+                self.errors.push(err);
+                None
+            }
+        }
+    }
+
+    /// Ends the build if anything went wrong so far, unless told to keep going
+    fn checkpoint(&mut self) -> Result<(), Vec<Error>> {
+        if self.keep_going || self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+}
+
+/// Renders every page in `input_dir` into `output_dir` with the layouts in `templates_dir`
+/// and copies over its static files, skipping anything that has not changed since the last build
+pub fn build(
+    input_dir: &Path,
+    templates_dir: &Path,
+    output_dir: &Path,
+    options: &BuildOptions,
+) -> Result<(), Vec<Error>> {
+    let config = config::get();
+    let mut errors = Errors {
+        errors: Vec::new(),
+        keep_going: options.keep_going,
+    };
+    let paths = read_dir(input_dir).map_err(|err| vec![err])?;
+    let static_dir = input_dir.join("static");
+    for dir in [output_dir, &static_dir] {
+        std::fs::create_dir_all(dir)
+            .map_err(Error::io("create", dir))
+            .map_err(|err| vec![err])?;
+    }
+    let mut cache = Cache::load(&config.root, output_dir);
+    let (templates, template_errors) = Templates::load(templates_dir, options.html_style());
+    errors.errors.extend(template_errors);
+    let mut paths = paths
+        .into_iter()
+        .filter(|x| x.is_file() && x.extension().is_some_and(|x| x == "md"))
+        .collect::<Vec<_>>();
+    // read_dir order is platform dependent, keep the output stable
+    paths.sort();
+    let pages = paths
+        .into_par_iter()
+        .map(Page::load)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter_map(|x| errors.collect(x))
+        .collect::<Vec<_>>();
+    let now = chrono::Utc::now().fixed_offset();
+    let pages = pages
+        .into_iter()
+        .filter(|x| x.is_published(now, options))
+        .collect::<Vec<_>>();
+    // No page may replace anything else that is written
+    let mut outputs = generated_outputs(&pages);
+    let mut pages = pages
+        .into_iter()
+        .filter(
+            |page| match outputs.entry(permalink::output_path(&page.url)) {
+                Entry::Occupied(other) => {
+                    errors.errors.push(Error::DuplicateUrl {
+                        url: page.url.clone(),
+                        first: other.get().clone(),
+                        second: page.path.clone(),
+                    });
+                    false
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(page.path.display().to_string());
+                    true
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    errors.checkpoint()?;
+    // Every page has to be known before any of them is rendered
+    errors.errors.extend(xref::link(&mut pages));
+    let (static_files, static_errors) = files::walk(&static_dir, "static");
+    errors.errors.extend(static_errors);
+    let (images, image_errors) = Images::scan(&static_files, &cache);
+    errors.errors.extend(image_errors);
+    let (assets, asset_errors) = assets::collect(&static_dir, &static_files);
+    errors.errors.extend(asset_errors);
+    errors.checkpoint()?;
+    // Pages link to them by the URLs made from their contents
+    assets::set(&assets);
+    let images = Arc::new(images);
+    for page in &mut pages {
+        page.images = images.clone();
+    }
+
+    let (taxonomies, taxonomy_errors) = taxonomies(&pages);
+    errors.errors.extend(taxonomy_errors);
+    errors.checkpoint()?;
+    // Pages as templates see them, in the same order as `pages`
+    let contexts = pages
+        .iter()
+        .map(|x| Value::from_serialize(PageContext::new(x, &taxonomies)))
+        .collect::<Vec<_>>();
+    let mut newest_first = (0..pages.len()).collect::<Vec<_>>();
+    newest_first.sort_by_key(|x| Reverse(pages[*x].timestamp));
+    // What every template gets to see
+    let site = context! {
+        site => config,
+        pages => newest_first.iter().map(|x| &contexts[*x]).collect::<Vec<_>>(),
+    };
+
+    write_index(
+        &mut cache,
+        &mut errors,
+        &templates,
+        &site,
+        &newest_first,
+        &pages,
+        &contexts,
+    );
+
+    // Everything a page's output depends on besides its own source
+    let site_key = cache::hash([
+        TEMPLATE_VERSION,
+        env!("CARGO_PKG_VERSION"),
+        &format!("{:?}", options.html_style()),
+        &format!("{config:?}"),
+        templates.key(),
+        &images.key(),
+        &cache::hash(assets.values().map(|x| &x.output)),
+    ]);
+    // Templates are handed the whole page list, so then every page depends on every other
+    let listing_key = if templates.is_empty() {
+        String::new()
+    } else {
+        cache::hash(pages.iter().flat_map(|x| [&x.url, &x.source_hash]))
+    };
+    let stale = pages
+        .iter()
+        .map(|page| (page, permalink::output_path(&page.url)))
+        .filter(|(page, output)| {
+            // The titles and URLs of the pages it links to or is linked from
+            let links_key = cache::hash(
+                page.references
+                    .values()
+                    .chain(&page.backlinks)
+                    .flat_map(|x| [&x.url, &x.title]),
+            );
+            !cache.is_fresh(
+                output,
+                cache::hash([&site_key, &listing_key, &links_key, &page.source_hash]),
+            )
+        })
+        .collect::<Vec<_>>();
+    let failed = stale
+        .into_par_iter()
+        .filter_map(|(page, output)| {
+            let context = context! {
+                page => PageContext::new(page, &taxonomies).with_content(page),
+                ..site.clone()
+            };
+            let toc = page.toc();
+            let path = output_dir.join(&output);
+            let result = templates.render_to_file("page.html", &output, &path, context, trowel_html! {
+                !BlogPageBase(title: {Some(&page.title)}, timestamp: {Some(page.timestamp.to_rfc2822())}, robots: {(page.draft || page.noindex).then_some("noindex")}) {
+                    {taxonomies[1].links(&page.categories)},
+                    {taxonomies[0].links(&page.tags)},
+                    {page.show_toc.then(|| toc::nav(&toc))},
+                    {Raw(page.body())},
+                    {backlinks(&page.backlinks)}
+                }
+            });
+            result.err().map(|err| (output, err))
+        })
+        .collect::<Vec<_>>();
+    for (output, err) in failed {
+        // Make sure it is tried again next time
+        cache.invalidate(&output);
+        errors.errors.push(err);
+    }
+    errors.checkpoint()?;
+
+    for taxonomy in &taxonomies {
+        write_taxonomy(
+            &mut cache,
+            &mut errors,
+            &templates,
+            &site,
+            taxonomy,
+            &pages,
+            &contexts,
+        );
+    }
+    for asset in assets.values() {
+        errors.collect(cache.write(&asset.output, &asset.contents));
+    }
+    for (output, xml) in [
+        ("feed.xml", feed::rss(&pages, config)),
+        ("atom.xml", feed::atom(&pages, config)),
+        ("sitemap.xml", sitemap::sitemap(&pages, &taxonomies, config)),
+    ] {
+        let xml = xml.map_err(|source| Error::Render {
+            output: output.to_string(),
+            source,
+        });
+        write_rendered(&mut cache, &mut errors, output, xml);
+    }
+    errors.collect(cache.write("robots.txt", sitemap::robots(config)));
+    errors.checkpoint()?;
+
+    errors.errors.extend(images.write(&mut cache, output_dir));
+    copy_static_content(&mut cache, &mut errors, &images, &static_files);
+    errors.checkpoint()?;
+
+    let stats = cache.finish().map_err(|err| vec![err])?;
+    for output in &stats.written {
+        println!("  wrote {output}");
+    }
+    for output in &stats.removed {
+        println!("  removed {output}");
+    }
+    println!("Built {}: {stats}", output_dir.display());
+
+    let sources = pages
+        .iter()
+        .map(|x| {
+            (
+                PathBuf::from(permalink::output_path(&x.url)),
+                x.path.clone(),
+            )
+        })
+        .collect();
+    if let Some(report) = errors.collect(linkcheck::check(output_dir, &sources)) {
+        for url in &report.external {
+            println!("  external {url}");
+        }
+        println!(
+            "Checked {} links in {} pages: {} broken, {} external not checked",
+            report.links,
+            report.pages,
+            report.broken.len(),
+            report.external.len()
+        );
+        for err in report.broken {
+            if options.strict {
+                errors.errors.push(err);
+            } else {
+                eprintln!("warning: {err}");
+            }
+        }
+    }
+    if errors.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.errors)
+    }
+}
+
+fn taxonomies(pages: &[Page]) -> ([Taxonomy; 2], Vec<Error>) {
+    let (tags, mut errors) = Taxonomy::collect("tags", "Tags", pages, |x| &x.tags);
+    let (categories, category_errors) =
+        Taxonomy::collect("categories", "Categories", pages, |x| &x.categories);
+    errors.extend(category_errors);
+    ([tags, categories], errors)
+}
+
+/// Everything written besides the posts of `pages`, by output path, with what it is
+fn generated_outputs(pages: &[Page]) -> HashMap<String, String> {
+    let mut outputs = HashMap::new();
+    for n in 1..=index_page_count(pages.len()) {
+        let name = if n == 1 {
+            "the index".to_string()
+        } else {
+            format!("page {n} of the index")
+        };
+        outputs.insert(permalink::output_path(&pagination_url(n)), name);
+    }
+    // Their errors are reported when they are collected for the build itself
+    let (taxonomies, _) = taxonomies(pages);
+    for taxonomy in taxonomies {
+        outputs.insert(
+            permalink::output_path(&taxonomy.index_url()),
+            format!("the {} page", taxonomy.name),
+        );
+        for term in taxonomy.terms.values() {
+            outputs.insert(
+                permalink::output_path(&taxonomy.term_url(&term.slug)),
+                format!("the {} page of {:?}", taxonomy.name, term.name),
+            );
+        }
+    }
+    for (output, name) in [
+        ("feed.xml", "the RSS feed"),
+        ("atom.xml", "the Atom feed"),
+        ("sitemap.xml", "the sitemap"),
+        ("robots.txt", "robots.txt"),
+    ] {
+        outputs.insert(output.to_string(), name.to_string());
+    }
+    outputs
+}
+
+/// Writes `contents` to `output` if it could be rendered
+fn write_rendered(
+    cache: &mut Cache,
+    errors: &mut Errors,
+    output: &str,
+    contents: Result<String, Error>,
+) {
+    errors.collect(contents.and_then(|x| cache.write(output, x)));
+}
+
+/// Paths of everything in `dir`
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    std::fs::read_dir(dir)
+        .and_then(|entries| entries.map(|x| Ok(x?.path())).collect())
+        .map_err(Error::io("read", dir))
+}
+
+/// Writes the index listing of `order`, indices into `pages` newest first,
+/// split into pages of `config.paginate` posts
+fn write_index(
+    cache: &mut Cache,
+    errors: &mut Errors,
+    templates: &Templates,
+    site: &Value,
+    order: &[usize],
+    pages: &[Page],
+    contexts: &[Value],
+) {
+    let config = config::get();
+    let chunks = if config.paginate == 0 || order.is_empty() {
+        vec![order]
+    } else {
+        order.chunks(config.paginate).collect()
+    };
+    let total = chunks.len();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let current = i + 1;
+        let title = if current == 1 {
+            config.title.clone()
+        } else {
+            format!("{} - Page {current}", config.title)
+        };
+        let prev = (current > 1).then(|| pagination_url(current - 1));
+        let next = (current < total).then(|| pagination_url(current + 1));
+        let context = context! {
+            paginator => context! {
+                current,
+                total,
+                prev,
+                next,
+                pages => chunk.iter().map(|x| &contexts[*x]).collect::<Vec<_>>(),
+            },
+            ..site.clone()
+        };
+        let output = permalink::output_path(&pagination_url(current));
+        let html = templates.render(
+            "index.html",
+            &output,
+            context,
+            trowel_html! {
+                !BlogPageBase(title: {Some(&title)}, prev: {prev.as_ref()}, next: {next.as_ref()}) {
+                    {page_list(chunk.iter().map(|x| &pages[*x]))},
+                    !Pagination(current: {Some(current)}, total: {Some(total)}) {}
+                }
+            },
+        );
+        write_rendered(cache, errors, &output, html);
+    }
+}
+
+fn write_taxonomy(
+    cache: &mut Cache,
+    errors: &mut Errors,
+    templates: &Templates,
+    site: &Value,
+    taxonomy: &Taxonomy,
+    pages: &[Page],
+    contexts: &[Value],
+) {
+    let term_context = |term: &Term| {
+        context! {
+            name => &term.name,
+            url => taxonomy.term_url(&term.slug),
+            pages => term.pages.iter().map(|x| &contexts[*x]).collect::<Vec<_>>(),
+        }
+    };
+    let taxonomy_context = context! {
+        name => taxonomy.name,
+        title => taxonomy.title,
+        url => taxonomy.index_url(),
+        terms => taxonomy.terms.values().map(term_context).collect::<Vec<_>>(),
+    };
+
+    let context = context! { taxonomy => taxonomy_context.clone(), ..site.clone() };
+    let output = permalink::output_path(&taxonomy.index_url());
+    let index = templates.render(
+        "taxonomy.html",
+        &output,
+        context,
+        trowel_html! {
+            !BlogPageBase(title: {Some(taxonomy.title)}) {
+                ul(style: "list-style-type:none;") {
+                    {
+                        taxonomy.terms.values().map(|term| {
+                            trowel_html! { move
+                                li {
+                                    a(href: {Some(taxonomy.term_url(&term.slug))}) {
+                                        {term.name.as_str()}
+                                    },
+                                    " ",
+                                    sub {
+                                        {format!("({})", term.pages.len())}
+                                    }
+                                }
+                            }
+                        }).collect::<Vec<_>>()
+                    }
+                }
+            }
+        },
+    );
+    write_rendered(cache, errors, &output, index);
+
+    for term in taxonomy.terms.values() {
+        let context = context! {
+            taxonomy => taxonomy_context.clone(),
+            term => term_context(term),
+            ..site.clone()
+        };
+        let output = permalink::output_path(&taxonomy.term_url(&term.slug));
+        let title = format!("{}: {}", taxonomy.title, term.name);
+        let html = templates.render(
+            "term.html",
+            &output,
+            context,
+            trowel_html! {
+                !BlogPageBase(title: {Some(&title)}) {
+                    p {
+                        a(href: {Some(taxonomy.index_url())}) {
+                            {format!("All {}", taxonomy.title.to_lowercase())}
+                        }
+                    },
+                    {page_list(term.pages.iter().map(|x| &pages[*x]))}
+                }
+            },
+        );
+        write_rendered(cache, errors, &output, html);
+    }
+}
+
+/// Copies the `files` of the static directory to the output, except for the
+/// `images`, which are written by [`Images::write`]
+fn copy_static_content(
+    cache: &mut Cache,
+    errors: &mut Errors,
+    images: &Images,
+    files: &[(PathBuf, String)],
+) {
+    for (src, output) in files {
+        if !images.contains(output) {
+            errors.collect(cache.copy(src, output));
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+pub const CACHE_DIR: &str = ".nakssg-cache";
+const MANIFEST_VERSION: &str = "nakssg-cache 1";
+
+/// Hex encoded SHA-256 of everything in `parts`
+pub fn hash<T: AsRef<[u8]>>(parts: impl IntoIterator<Item = T>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        let part = part.as_ref();
+        // Length prefixed so ["ab", "c"] and ["a", "bc"] differ
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+#[derive(Clone, PartialEq)]
+struct Entry {
+    key: String,
+    /// Size and modification time of the source, lets unchanged static
+    /// files skip being hashed again
+    size: u64,
+    modified: u128,
+}
+
+#[derive(Default)]
+pub struct Stats {
+    pub written: Vec<String>,
+    pub unchanged: usize,
+    pub removed: Vec<String>,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} written, {} unchanged, {} removed",
+            self.written.len(),
+            self.unchanged,
+            self.removed.len()
+        )
+    }
+}
+
+/// Tracks what was written to the output directory by the last build, keyed on
+/// a hash of whatever went into each output, so unchanged outputs can be skipped
+/// and outputs whose sources are gone can be removed
+pub struct Cache {
+    /// Where the manifests are kept, [`CACHE_DIR`] in the site's directory
+    dir: PathBuf,
+    output_dir: PathBuf,
+    old: BTreeMap<String, Entry>,
+    new: BTreeMap<String, Entry>,
+    pub stats: Stats,
+    finished: bool,
+}
+
+impl Cache {
+    /// Reads what the last build into `output_dir` of the site in `site_dir` wrote
+    pub fn load(site_dir: &Path, output_dir: &Path) -> Self {
+        let mut cache = Cache {
+            dir: site_dir.join(CACHE_DIR),
+            output_dir: output_dir.to_path_buf(),
+            old: BTreeMap::new(),
+            new: BTreeMap::new(),
+            stats: Stats::default(),
+            finished: false,
+        };
+        let Ok(manifest) = std::fs::read_to_string(cache.manifest_path()) else {
+            return cache;
+        };
+        let mut lines = manifest.lines();
+        if lines.next() != Some(MANIFEST_VERSION)
+            || lines.next() != Some(cache.output_id().as_str())
+        {
+            // Made by another version, or for another output directory with the same hash
+            return cache;
+        }
+        for line in lines {
+            let mut fields = line.split('\t');
+            let (Some(output), Some(key), Some(size), Some(modified)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            cache.old.insert(
+                output.to_string(),
+                Entry {
+                    key: key.to_string(),
+                    size: size.parse().unwrap_or(0),
+                    modified: modified.parse().unwrap_or(0),
+                },
+            );
+        }
+        cache
+    }
+
+    /// Identifies the output directory no matter how it was spelled
+    fn output_id(&self) -> String {
+        std::fs::canonicalize(&self.output_dir)
+            .unwrap_or(self.output_dir.clone())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Every output directory gets a manifest of its own, so building into another one
+    /// in between, e.g. with `serve`, does not throw away what is known about this one
+    fn manifest_path(&self) -> PathBuf {
+        self.dir
+            .join(format!("manifest-{}", &hash([self.output_id()])[..16]))
+    }
+
+    fn record(&mut self, output: &str, entry: Entry) -> bool {
+        let fresh = self.old.get(output) == Some(&entry) && self.output_dir.join(output).is_file();
+        if fresh {
+            self.stats.unchanged += 1;
+        } else {
+            self.stats.written.push(output.to_string());
+        }
+        self.new.insert(output.to_string(), entry);
+        fresh
+    }
+
+    /// Whether `output` was last built from the same `key` and still exists,
+    /// if not the caller is expected to write it
+    pub fn is_fresh(&mut self, output: &str, key: String) -> bool {
+        let entry = Entry {
+            key,
+            size: 0,
+            modified: 0,
+        };
+        self.record(output, entry)
+    }
+
+    /// Writes `contents` to `output` unless it already holds exactly that
+    pub fn write(&mut self, output: &str, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+        let contents = contents.as_ref();
+        if !self.is_fresh(output, hash([contents])) {
+            let path = self.output_dir.join(output);
+            create_parent(&path)?;
+            std::fs::write(&path, contents).map_err(Error::io("write", path))?;
+        }
+        Ok(())
+    }
+
+    /// Copies `src` to `output` unless its contents have not changed
+    pub fn copy(&mut self, src: &Path, output: &str) -> Result<(), Error> {
+        let entry = self.source_entry(src, output)?;
+        if !self.record(output, entry) {
+            let path = self.output_dir.join(output);
+            create_parent(&path)?;
+            std::fs::copy(src, &path).map_err(Error::io("write", path))?;
+        }
+        Ok(())
+    }
+
+    /// Writes `src` to `output` through `transform` unless its contents have not changed
+    ///
+    /// Keyed on `src` alone, `transform` has to be the same from one build to the next.
+    pub fn write_from(
+        &mut self,
+        src: &Path,
+        output: &str,
+        transform: impl FnOnce(Vec<u8>) -> Vec<u8>,
+    ) -> Result<(), Error> {
+        let entry = self.source_entry(src, output)?;
+        if !self.record(output, entry) {
+            let contents = std::fs::read(src).map_err(Error::io("read", src))?;
+            let path = self.output_dir.join(output);
+            create_parent(&path)?;
+            std::fs::write(&path, transform(contents)).map_err(Error::io("write", path))?;
+        }
+        Ok(())
+    }
+
+    /// Hash of the contents of `src`, which is written to `output`, without reading it
+    /// again if its size and modification time are what they were last build
+    pub fn source_hash(&self, src: &Path, output: &str) -> Result<String, Error> {
+        Ok(self.source_entry(src, output)?.key)
+    }
+
+    fn source_entry(&self, src: &Path, output: &str) -> Result<Entry, Error> {
+        let metadata = std::fs::metadata(src).map_err(Error::io("read", src))?;
+        let size = metadata.len();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_nanos())
+            .unwrap_or(0);
+        let key = match self.old.get(output) {
+            Some(old) if old.size == size && old.modified == modified => old.key.clone(),
+            _ => hash([std::fs::read(src).map_err(Error::io("read", src))?]),
+        };
+        Ok(Entry {
+            key,
+            size,
+            modified,
+        })
+    }
+
+    /// Forgets `output` when it could not be written after all, so that it is
+    /// removed and the next build tries again
+    pub fn invalidate(&mut self, output: &str) {
+        if self.new.remove(output).is_some() {
+            self.stats.written.retain(|x| x != output);
+        }
+        // Never matches, in case the build stops before it would be removed
+        if let Some(old) = self.old.get_mut(output) {
+            old.key.clear();
+        }
+    }
+
+    /// Removes outputs that were not produced by this build and saves the manifest
+    pub fn finish(mut self) -> Result<Stats, Error> {
+        for output in self.old.keys() {
+            if self.new.contains_key(output) {
+                continue;
+            }
+            let path = self.output_dir.join(output);
+            if path.is_file() {
+                std::fs::remove_file(&path).map_err(Error::io("remove", &path))?;
+                self.stats.removed.push(output.clone());
+            }
+        }
+
+        self.finished = true;
+        self.save(&self.new)?;
+        Ok(std::mem::take(&mut self.stats))
+    }
+
+    fn save(&self, entries: &BTreeMap<String, Entry>) -> Result<(), Error> {
+        let manifest = self.manifest_path();
+        self.write_manifest(&manifest, entries)
+            .map_err(Error::io("write", manifest))
+    }
+
+    fn write_manifest(
+        &self,
+        manifest: &Path,
+        entries: &BTreeMap<String, Entry>,
+    ) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let file = std::fs::File::create(manifest)?;
+        let mut writer = std::io::BufWriter::new(file);
+        writeln!(writer, "{MANIFEST_VERSION}")?;
+        writeln!(writer, "{}", self.output_id())?;
+        for (output, entry) in entries {
+            writeln!(
+                writer,
+                "{output}\t{}\t{}\t{}",
+                entry.key, entry.size, entry.modified
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+/// A build that stopped early still saves what it wrote, along with what the last
+/// build wrote that it did not get to, so that neither is written again for nothing
+/// and nothing is left behind when its source goes away
+impl Drop for Cache {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let mut entries = self.old.clone();
+        entries.extend(std::mem::take(&mut self.new));
+        if let Err(err) = self.save(&entries) {
+            eprintln!("warning: {err}");
+        }
+    }
+}
+
+/// Creates the directory `path` goes in
+pub fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent).map_err(Error::io("create", parent)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A site directory of its own for each test, removed afterwards
+    struct Site(PathBuf);
+
+    impl Site {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("nakssg-cache-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("dist")).unwrap();
+            Site(dir)
+        }
+
+        fn cache(&self) -> Cache {
+            Cache::load(&self.0, &self.0.join("dist"))
+        }
+
+        fn output(&self, output: &str) -> PathBuf {
+            self.0.join("dist").join(output)
+        }
+    }
+
+    impl Drop for Site {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn skips_unchanged_outputs() {
+        let site = Site::new("unchanged");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert!(!cache.is_fresh("b.xml", "1".to_string()));
+        std::fs::write(site.output("b.xml"), "b").unwrap();
+        let stats = cache.finish().unwrap();
+        assert_eq!(stats.written, ["a.html", "b.xml"]);
+
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert!(cache.is_fresh("b.xml", "1".to_string()));
+        let stats = cache.finish().unwrap();
+        assert!(stats.written.is_empty());
+        assert_eq!(stats.unchanged, 2);
+
+        let mut cache = site.cache();
+        cache.write("a.html", "changed").unwrap();
+        assert!(!cache.is_fresh("b.xml", "2".to_string()));
+        assert_eq!(cache.finish().unwrap().written, ["a.html", "b.xml"]);
+        assert_eq!(
+            std::fs::read_to_string(site.output("a.html")).unwrap(),
+            "changed"
+        );
+    }
+
+    #[test]
+    fn rewrites_outputs_that_are_gone() {
+        let site = Site::new("gone");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.finish().unwrap();
+        std::fs::remove_file(site.output("a.html")).unwrap();
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert_eq!(cache.finish().unwrap().written, ["a.html"]);
+        assert!(site.output("a.html").is_file());
+    }
+
+    #[test]
+    fn removes_outputs_no_longer_built() {
+        let site = Site::new("removed");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.write("b/c.html", "c").unwrap();
+        cache.finish().unwrap();
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert_eq!(cache.finish().unwrap().removed, ["b/c.html"]);
+        assert!(!site.output("b/c.html").exists());
+    }
+
+    #[test]
+    fn invalidated_outputs_are_tried_again() {
+        let site = Site::new("invalidated");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.finish().unwrap();
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.invalidate("a.html");
+        let stats = cache.finish().unwrap();
+        assert_eq!(stats.removed, ["a.html"]);
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert_eq!(cache.finish().unwrap().written, ["a.html"]);
+    }
+
+    #[test]
+    fn keeps_what_a_failed_build_knew() {
+        let site = Site::new("failed");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.write("b.html", "b").unwrap();
+        cache.finish().unwrap();
+        // Stops after writing one output and invalidating another
+        let mut cache = site.cache();
+        cache.write("c.html", "c").unwrap();
+        cache.invalidate("b.html");
+        drop(cache);
+
+        let mut cache = site.cache();
+        for output in ["a.html", "b.html", "c.html"] {
+            cache.write(output, &output[..1]).unwrap();
+        }
+        assert_eq!(cache.finish().unwrap().written, ["b.html"]);
+    }
+
+    #[test]
+    fn copies_files_unless_they_changed() {
+        let site = Site::new("copies");
+        let src = site.0.join("photo.jpg");
+        std::fs::write(&src, "jpeg").unwrap();
+        let mut cache = site.cache();
+        cache.copy(&src, "static/photo.jpg").unwrap();
+        let hash = cache.source_hash(&src, "static/photo.jpg").unwrap();
+        cache.finish().unwrap();
+        assert_eq!(hash, super::hash(["jpeg"]));
+
+        let mut cache = site.cache();
+        cache.copy(&src, "static/photo.jpg").unwrap();
+        assert_eq!(cache.finish().unwrap().unchanged, 1);
+        std::fs::write(&src, "jpeg, but longer").unwrap();
+        let mut cache = site.cache();
+        cache
+            .write_from(&src, "static/photo.jpg", |x| x[..4].to_vec())
+            .unwrap();
+        assert_eq!(cache.finish().unwrap().written, ["static/photo.jpg"]);
+        assert_eq!(
+            std::fs::read(site.output("static/photo.jpg")).unwrap(),
+            b"jpeg"
+        );
+    }
+
+    #[test]
+    fn keeps_a_manifest_per_output_directory() {
+        let site = Site::new("per-output");
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        cache.finish().unwrap();
+        let mut other = Cache::load(&site.0, &site.0.join("other"));
+        other.write("b.html", "b").unwrap();
+        other.finish().unwrap();
+        let mut cache = site.cache();
+        cache.write("a.html", "a").unwrap();
+        assert_eq!(cache.finish().unwrap().unchanged, 1);
+        assert!(site.0.join(CACHE_DIR).is_dir());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::AssetOptions, error::Error, frontmatter::line_column, images::ImageOptions,
+    markdown::MarkdownOptions, permalink, sitemap::RobotsOptions, toc::TocOptions,
+};
+
+/// Site wide settings, read from `nakssg.toml`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub title: String,
+    /// Absolute URL the site is served from, without a trailing slash
+    pub base_url: String,
+    pub author: Option<String>,
+    pub language: String,
+    pub menu: Vec<MenuEntry>,
+    pub stylesheets: Vec<String>,
+    /// Maximum number of posts in feeds
+    pub feed_limit: usize,
+    /// Posts per page of the index, 0 to put them all on one page
+    pub paginate: usize,
+    /// Where posts are written, see [`crate::permalink::expand`]
+    pub permalink: String,
+    /// Where the list of tags or categories is written, with `:taxonomy` for its name
+    pub taxonomy_permalink: String,
+    /// Where the posts of a single tag or category are written, with `:taxonomy` and
+    /// the term's `:slug`
+    pub term_permalink: String,
+    /// Where the index pages after the first are written, with `:page` for the number
+    pub pagination_permalink: String,
+    /// Colours of highlighted code, one of syntect's default themes
+    pub highlight_theme: String,
+    pub markdown: MarkdownOptions,
+    /// Heading levels in tables of contents
+    pub toc: TocOptions,
+    pub robots: RobotsOptions,
+    pub images: ImageOptions,
+    pub assets: AssetOptions,
+    /// Anything else the site wants to make available to components
+    pub params: BTreeMap<String, toml::Value>,
+    /// Directory the config is in, which the site's cache is kept in
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MenuEntry {
+    pub name: String,
+    pub url: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: "Blog".to_string(),
+            base_url: "http://localhost".to_string(),
+            author: None,
+            language: "en".to_string(),
+            menu: vec![MenuEntry {
+                name: "Home".to_string(),
+                url: "/".to_string(),
+            }],
+            stylesheets: vec![
+                "https://unpkg.com/normalize.css@7.0.0/normalize.css".to_string(),
+                "https://unpkg.com/sakura.css@1.5.0/css/sakura-pink.css".to_string(),
+            ],
+            feed_limit: 20,
+            paginate: 10,
+            permalink: "/:slug.html".to_string(),
+            taxonomy_permalink: "/:taxonomy/index.html".to_string(),
+            term_permalink: "/:taxonomy/:slug.html".to_string(),
+            pagination_permalink: "/page/:page/".to_string(),
+            highlight_theme: "InspiredGitHub".to_string(),
+            markdown: MarkdownOptions::default(),
+            toc: TocOptions::default(),
+            robots: RobotsOptions::default(),
+            images: ImageOptions::default(),
+            assets: AssetOptions::default(),
+            params: BTreeMap::new(),
+            root: PathBuf::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config at `path`, falling back to the defaults if there is none
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let root = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if !path.exists() {
+            return Ok(Config {
+                root,
+                ..Config::default()
+            });
+        }
+        let source = std::fs::read_to_string(path).map_err(Error::io("read", path))?;
+        let mut config: Config = toml::from_str(&source).map_err(|err| {
+            let (line, column) = line_column(&source, err.span().map(|x| x.start).unwrap_or(0));
+            Error::Config {
+                path: path.to_path_buf(),
+                line,
+                column,
+                reason: err.message().trim_end().to_string(),
+            }
+        })?;
+        config.base_url = config.base_url.trim_end_matches('/').to_string();
+        config.root = root;
+        for (key, pattern, placeholders) in [
+            (
+                "permalink",
+                &config.permalink,
+                &["year", "month", "day", "slug"][..],
+            ),
+            (
+                "taxonomy_permalink",
+                &config.taxonomy_permalink,
+                &["taxonomy"],
+            ),
+            (
+                "term_permalink",
+                &config.term_permalink,
+                &["taxonomy", "slug"],
+            ),
+            (
+                "pagination_permalink",
+                &config.pagination_permalink,
+                &["page"],
+            ),
+        ] {
+            permalink::check(pattern, placeholders).map_err(|reason| {
+                let (line, column) = key_location(&source, key);
+                Error::Config {
+                    path: path.to_path_buf(),
+                    line,
+                    column,
+                    reason: format!("{key}: {reason}"),
+                }
+            })?;
+        }
+        Ok(config)
+    }
+
+    pub fn absolute_url(&self, url: &str) -> String {
+        format!("{}{}", self.base_url, url)
+    }
+}
+
+/// 1-based line and column of the value of the top level key `key` in `source`
+fn key_location(source: &str, key: &str) -> (usize, usize) {
+    source
+        .lines()
+        .enumerate()
+        .find_map(|(i, line)| {
+            let rest = line.strip_prefix(key)?.trim_start().strip_prefix('=')?;
+            let value = rest.trim_start();
+            Some((i + 1, line.len() - value.len() + 1))
+        })
+        .unwrap_or((1, 1))
+}
+
+/// Leaked so [`get`] can hand out `'static` references, it only changes when `serve`
+/// picks up an edit to the config
+static CONFIG: RwLock<Option<&'static Config>> = RwLock::new(None);
+
+/// Makes `config` available through [`get`], replacing the one set before
+pub fn set(config: Config) {
+    *CONFIG.write().unwrap() = Some(Box::leak(Box::new(config)));
+}
+
+/// The site config, or the defaults if none was set
+pub fn get() -> &'static Config {
+    if let Some(config) = *CONFIG.read().unwrap() {
+        return config;
+    }
+    CONFIG
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Box::leak(Box::default()))
+}
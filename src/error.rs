@@ -0,0 +1,213 @@
+use std::{fmt::Display, path::PathBuf};
+
+use crate::frontmatter::FrontMatterError;
+
+/// Anything that can go wrong while running a command, each pointing at the file
+/// it is about
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        /// What was being done, e.g. `read`
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    FrontMatter(FrontMatterError),
+    Config {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    /// nakssg needs file names to be valid UTF-8 to turn them into URLs
+    NonUtf8Path(PathBuf),
+    /// A page's permalink or slug that cannot be turned into an output file
+    Permalink {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A tag or category that is published at the same URL as another one
+    TermCollision {
+        path: PathBuf,
+        taxonomy: &'static str,
+        name: String,
+        other: String,
+    },
+    DuplicateUrl {
+        url: String,
+        /// What is already published there, a page's source file or a generated page
+        first: String,
+        second: PathBuf,
+    },
+    Template {
+        template: PathBuf,
+        /// The output that was being rendered
+        output: String,
+        source: minijinja::Error,
+    },
+    /// The built in layout, a feed or the sitemap failed to render
+    Render {
+        output: String,
+        source: trowel::html::Error,
+    },
+    BrokenLink {
+        /// The output the link is in
+        file: PathBuf,
+        line: usize,
+        /// The page that output was rendered from, if it was
+        page: Option<PathBuf>,
+        reason: String,
+    },
+    /// A cross reference to a page that does not exist
+    UnknownReference {
+        file: PathBuf,
+        line: usize,
+        name: String,
+    },
+    Image {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A stylesheet or script that could not be minified
+    Asset {
+        name: String,
+        reason: String,
+    },
+    AlreadyExists(PathBuf),
+    UnknownTheme {
+        name: String,
+        available: Vec<String>,
+    },
+    Threads(rayon::ThreadPoolBuildError),
+    Listen {
+        port: u16,
+        source: std::io::Error,
+    },
+    Watch {
+        path: PathBuf,
+        source: notify::Error,
+    },
+}
+
+impl Error {
+    /// For `map_err`, wraps an I/O error from doing `action` to `path`
+    pub fn io(
+        action: &'static str,
+        path: impl Into<PathBuf>,
+    ) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Io {
+            action,
+            path,
+            source,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io {
+                action,
+                path,
+                source,
+            } => write!(f, "{}: could not {action}: {source}", path.display()),
+            Error::FrontMatter(err) => write!(f, "{err}"),
+            Error::Config {
+                path,
+                line,
+                column,
+                reason,
+            } => write!(f, "{}:{line}:{column}: {reason}", path.display()),
+            Error::NonUtf8Path(path) => {
+                write!(f, "{}: file name is not valid UTF-8", path.display())
+            }
+            Error::Permalink { path, reason } => {
+                write!(f, "{}: bad permalink: {reason}", path.display())
+            }
+            Error::TermCollision {
+                path,
+                taxonomy,
+                name,
+                other,
+            } => write!(
+                f,
+                "{}: {taxonomy} {name:?} would be published at the same URL as {other:?}",
+                path.display()
+            ),
+            Error::DuplicateUrl { url, first, second } => write!(
+                f,
+                "{}: published at {url}, which {first} already is",
+                second.display(),
+            ),
+            Error::Template {
+                template,
+                output,
+                source,
+            } => write!(f, "{}: {source}, rendering {output}", template.display()),
+            Error::Render { output, source } => write!(f, "{output}: could not render: {source}"),
+            Error::BrokenLink {
+                file,
+                line,
+                page,
+                reason,
+            } => {
+                write!(f, "{}:{line}: {reason}", file.display())?;
+                match page {
+                    Some(page) => write!(f, " (from {})", page.display()),
+                    None => Ok(()),
+                }
+            }
+            Error::UnknownReference { file, line, name } => {
+                write!(f, "{}:{line}: no page named {name:?}", file.display())
+            }
+            Error::Image { path, reason } => {
+                write!(f, "{}: could not process image: {reason}", path.display())
+            }
+            Error::Asset { name, reason } => write!(f, "{name}: could not minify: {reason}"),
+            Error::AlreadyExists(path) => write!(f, "{}: already exists", path.display()),
+            Error::UnknownTheme { name, available } => write!(
+                f,
+                "unknown highlight theme {name:?}, expected one of {}",
+                available.join(", ")
+            ),
+            Error::Threads(err) => write!(f, "could not start worker threads: {err}"),
+            Error::Listen { port, source } => {
+                write!(f, "could not listen on port {port}: {source}")
+            }
+            Error::Watch { path, source } => {
+                write!(
+                    f,
+                    "{}: could not watch for changes: {source}",
+                    path.display()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Listen { source, .. } => Some(source),
+            Error::Template { source, .. } => Some(source),
+            Error::Render { source, .. } => Some(source),
+            Error::Threads(source) => Some(source),
+            Error::Watch { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<FrontMatterError> for Error {
+    fn from(err: FrontMatterError) -> Self {
+        Error::FrontMatter(err)
+    }
+}
+
+/// Prints every one of `errors`
+pub fn report(errors: &[Error]) {
+    for err in errors {
+        eprintln!("error: {err}");
+    }
+}
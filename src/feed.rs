@@ -0,0 +1,253 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, FixedOffset};
+use trowel::{
+    html::{self, escape_attribute},
+    trowel_html,
+    util::xml_to_string,
+};
+
+use crate::{config::Config, linkcheck, Page};
+
+/// `html` with root relative links and images made absolute, feed readers have no
+/// page to resolve them against
+fn absolute_urls(html: &str, config: &Config) -> String {
+    let absolute = |url: &str| match url.strip_prefix('/') {
+        Some(path) if !path.starts_with('/') => config.absolute_url(url),
+        _ => url.to_string(),
+    };
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(tag) = linkcheck::parse_tag(rest) else {
+            rewritten.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+        let source = &rest[..tag.len];
+        rest = &rest[tag.len..];
+        let attributes = tag
+            .attributes
+            .iter()
+            .map(|(name, value)| {
+                let value = match name.as_str() {
+                    "href" | "src" | "poster" => absolute(value),
+                    "srcset" => value
+                        .split(',')
+                        .map(|x| {
+                            let x = x.trim();
+                            let (url, descriptor) = x.split_once(' ').unwrap_or((x, ""));
+                            format!("{} {descriptor}", absolute(url))
+                                .trim_end()
+                                .to_string()
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    _ => return (name, value.clone()),
+                };
+                (name, value)
+            })
+            .collect::<Vec<_>>();
+        if attributes
+            .iter()
+            .zip(&tag.attributes)
+            .all(|(x, y)| x.1 == y.1)
+        {
+            rewritten.push_str(source);
+            continue;
+        }
+        rewritten.push('<');
+        rewritten.push_str(&tag.name);
+        for (name, value) in attributes {
+            rewritten.push_str(&format!(" {name}=\"{}\"", escape_attribute(&value)));
+        }
+        rewritten.push_str(if source.ends_with("/>") { " />" } else { ">" });
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Newest pages first, at most `config.feed_limit` of them
+fn feed_pages<'a>(pages: &'a [Page], config: &Config) -> Vec<&'a Page> {
+    let mut pages = pages.iter().collect::<Vec<_>>();
+    pages.sort_by_key(|x| Reverse(x.timestamp));
+    pages.truncate(config.feed_limit);
+    pages
+}
+
+/// When the last of `pages` changed, which is not always when the newest was posted
+fn last_change(pages: &[&Page]) -> Option<DateTime<FixedOffset>> {
+    pages.iter().map(|x| x.lastmod()).max()
+}
+
+pub fn rss(pages: &[Page], config: &Config) -> html::Result<String> {
+    let pages = feed_pages(pages, config);
+    let site_url = config.absolute_url("/");
+    let last_build = last_change(&pages).map(|x| x.to_rfc2822());
+    xml_to_string(trowel_html! {
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        rss(version: "2.0") {
+            channel {
+                title { {config.title.as_str()} },
+                link { {site_url.as_str()} },
+                description { {config.title.as_str()} },
+                language { {config.language.as_str()} },
+                generator { "nakssg" },
+                {last_build.map(|x| trowel_html! { move
+                    lastBuildDate { {x} }
+                })},
+                {
+                    pages.iter().map(|page| {
+                        let url = config.absolute_url(&page.url);
+                        trowel_html! { move
+                            item {
+                                title { {page.title.as_str()} },
+                                link { {url.as_str()} },
+                                guid(isPermaLink: "true") { {url.as_str()} },
+                                pubDate { {page.timestamp.to_rfc2822()} },
+                                // RSS expects an email address here
+                                {config.author.as_deref().filter(|x| x.contains('@')).map(|x| trowel_html! { move
+                                    author { {x} }
+                                })},
+                                {
+                                    page.tags.iter().chain(&page.categories).map(|x| trowel_html! { move
+                                        category { {x.as_str()} }
+                                    }).collect::<Vec<_>>()
+                                },
+                                description { {absolute_urls(page.body(), config)} }
+                            }
+                        }
+                    }).collect::<Vec<_>>()
+                }
+            }
+        }
+    })
+}
+
+pub fn atom(pages: &[Page], config: &Config) -> html::Result<String> {
+    let pages = feed_pages(pages, config);
+    let site_url = config.absolute_url("/");
+    let feed_url = config.absolute_url("/atom.xml");
+    let updated = last_change(&pages)
+        .unwrap_or_else(|| chrono::Utc::now().fixed_offset())
+        .to_rfc3339();
+    // Atom requires an author, fall back to the site itself
+    let author = config.author.as_deref().unwrap_or(config.title.as_str());
+    xml_to_string(trowel_html! {
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        feed(xmlns: "http://www.w3.org/2005/Atom") {
+            title { {config.title.as_str()} },
+            id { {site_url.as_str()} },
+            <link(href: {Some(&site_url)}),
+            <link(rel: "self", href: {Some(&feed_url)}),
+            updated { {updated} },
+            author {
+                name { {author} }
+            },
+            generator { "nakssg" },
+            {
+                pages.iter().map(|page| {
+                    let url = config.absolute_url(&page.url);
+                    trowel_html! { move
+                        entry {
+                            title { {page.title.as_str()} },
+                            id { {url.as_str()} },
+                            <link(href: {Some(&url)}),
+                            published { {page.timestamp.to_rfc3339()} },
+                            updated { {page.lastmod().to_rfc3339()} },
+                            {
+                                page.tags.iter().chain(&page.categories).map(|x| trowel_html! { move
+                                    <category(term: {Some(x)})
+                                }).collect::<Vec<_>>()
+                            },
+                            content(type: "html") { {absolute_urls(page.body(), config)} }
+                        }
+                    }
+                }).collect::<Vec<_>>()
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            base_url: "https://example.com".to_string(),
+            feed_limit: 2,
+            ..Config::default()
+        }
+    }
+
+    fn page(name: &str, timestamp: &str, updated: Option<&str>) -> Page {
+        let updated = updated.map_or(String::new(), |x| format!("updated: {x}\n"));
+        let source = format!("---\ntitle: {name}\ntimestamp: {timestamp}\n{updated}---\n");
+        Page::parse(Path::new(&format!("{name}.md")), &source).unwrap()
+    }
+
+    #[test]
+    fn makes_root_relative_urls_absolute() {
+        let html = "<p><a href=\"/a.html\">a</a> <a href=\"b.html\">b</a> \
+                    <a href=\"//cdn.example.org/c\">c</a> <a href=\"#d\">d</a></p>";
+        assert_eq!(
+            absolute_urls(html, &config()),
+            "<p><a href=\"https://example.com/a.html\">a</a> <a href=\"b.html\">b</a> \
+             <a href=\"//cdn.example.org/c\">c</a> <a href=\"#d\">d</a></p>"
+        );
+    }
+
+    #[test]
+    fn makes_every_url_in_a_srcset_absolute() {
+        let html =
+            "<img src=/a.png srcset=\"/a-480w.png 480w,/a.png 960w, https://x.org/b.png 2x\" />";
+        assert_eq!(
+            absolute_urls(html, &config()),
+            "<img src=\"https://example.com/a.png\" srcset=\"https://example.com/a-480w.png 480w, \
+             https://example.com/a.png 960w, https://x.org/b.png 2x\" />"
+        );
+    }
+
+    #[test]
+    fn leaves_tags_without_relative_urls_as_they_are() {
+        let html = "<a   href='https://x.org'  class=y>a &amp; b</a><video poster=/p.jpg>";
+        assert_eq!(
+            absolute_urls(html, &config()),
+            "<a   href='https://x.org'  class=y>a &amp; b</a><video poster=\"https://example.com/p.jpg\">"
+        );
+    }
+
+    #[test]
+    fn keeps_the_newest_pages() {
+        let pages = [
+            page("a", "2024-01-01T00:00:00Z", None),
+            page("b", "2024-03-01T00:00:00Z", None),
+            page("c", "2024-02-01T00:00:00Z", None),
+        ];
+        let config = config();
+        let titles = feed_pages(&pages, &config)
+            .iter()
+            .map(|x| x.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["b", "c"]);
+    }
+
+    #[test]
+    fn last_changed_when_any_page_was_updated() {
+        let pages = [
+            page("a", "2024-01-01T00:00:00Z", Some("2024-06-01T00:00:00Z")),
+            page("b", "2024-03-01T00:00:00Z", None),
+        ];
+        let pages = pages.iter().collect::<Vec<_>>();
+        assert_eq!(
+            last_change(&pages).unwrap().to_rfc3339(),
+            "2024-06-01T00:00:00+00:00"
+        );
+        assert!(last_change(&[]).is_none());
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/// Every file under `dir`, with the output it has when `dir` is copied to `output`,
+/// e.g. `static/photo.jpg`, in a stable order
+///
+/// Files with names that are not valid UTF-8 have no output, so they are returned
+/// as errors instead, along with any directory that could not be read.
+pub fn walk(dir: &Path, output: &str) -> (Vec<(PathBuf, String)>, Vec<Error>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    walk_into(dir, output, &mut files, &mut errors);
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    (files, errors)
+}
+
+fn walk_into(
+    dir: &Path,
+    output: &str,
+    files: &mut Vec<(PathBuf, String)>,
+    errors: &mut Vec<Error>,
+) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return errors.push(Error::io("read", dir)(err)),
+    };
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                errors.push(Error::io("read", dir)(err));
+                continue;
+            }
+        };
+        let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+            errors.push(Error::NonUtf8Path(path));
+            continue;
+        };
+        let output = if output.is_empty() {
+            name.to_string()
+        } else {
+            format!("{output}/{name}")
+        };
+        if path.is_dir() {
+            walk_into(&path, &output, files, errors);
+        } else {
+            files.push((path, output));
+        }
+    }
+}
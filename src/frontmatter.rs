@@ -0,0 +1,396 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Deserializer};
+use serde_path_to_error::Segment;
+
+use crate::markdown::MarkdownOptions;
+
+pub type Value = serde_yaml::Value;
+
+#[derive(Debug, Deserialize)]
+pub struct FrontMatter {
+    pub title: String,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub timestamp: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Replaces the file name in the page's permalink
+    pub slug: Option<String>,
+    /// Replaces the site's permalink pattern for this page
+    pub permalink: Option<String>,
+    /// Puts a table of contents above the page
+    #[serde(default)]
+    pub toc: bool,
+    /// Overrides the site's markdown options
+    #[serde(default)]
+    pub markdown: MarkdownOptions,
+    #[serde(default)]
+    pub draft: bool,
+    /// Keeps search engines away from the page, and it out of the sitemap
+    #[serde(default)]
+    pub noindex: bool,
+    /// When the page last changed, if not when it was posted
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub updated: Option<DateTime<FixedOffset>>,
+    /// Hidden from the build until this time
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub publish_date: Option<DateTime<FixedOffset>>,
+    /// Hidden from the build after this time
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub expiry_date: Option<DateTime<FixedOffset>>,
+    /// Every other key, for templates
+    #[serde(skip)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// The keys of [`FrontMatter`] that are not left to `extra`
+///
+/// Not `#[serde(flatten)]`, which buffers the whole block before handing out the
+/// fields, so errors in them would all point at the start of it.
+const FIELDS: &[&str] = &[
+    "title",
+    "timestamp",
+    "tags",
+    "categories",
+    "slug",
+    "permalink",
+    "toc",
+    "markdown",
+    "draft",
+    "noindex",
+    "updated",
+    "publish_date",
+    "expiry_date",
+];
+
+#[derive(Debug)]
+pub struct FrontMatterError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub reason: String,
+}
+
+impl Display for FrontMatterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for FrontMatterError {}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn delimiter(self) -> &'static str {
+        match self {
+            Format::Yaml => "---",
+            Format::Toml => "+++",
+        }
+    }
+}
+
+/// Splits the frontmatter block off the top of `source` and parses it,
+/// returning it along with the remaining markdown body
+pub fn parse(
+    file: impl AsRef<Path>,
+    source: &str,
+) -> Result<(FrontMatter, &str), FrontMatterError> {
+    let error = |line, column, reason: String| FrontMatterError {
+        file: file.as_ref().to_path_buf(),
+        line,
+        column,
+        reason,
+    };
+
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let (first, rest) = split_line(source);
+    let format = match first.trim_end() {
+        "---" => Format::Yaml,
+        "+++" => Format::Toml,
+        _ => {
+            return Err(error(
+                1,
+                1,
+                "expected a `---` (YAML) or `+++` (TOML) frontmatter block".to_string(),
+            ))
+        }
+    };
+
+    let mut block_len = 0;
+    let mut body = None;
+    let mut remaining = rest;
+    while !remaining.is_empty() {
+        let (line, next) = split_line(remaining);
+        if line.trim_end() == format.delimiter() {
+            body = Some(next);
+            break;
+        }
+        block_len += remaining.len() - next.len();
+        remaining = next;
+    }
+    let Some(body) = body else {
+        return Err(error(
+            1,
+            1,
+            format!(
+                "unterminated frontmatter, expected a closing `{}`",
+                format.delimiter()
+            ),
+        ));
+    };
+    let block = &rest[..block_len];
+
+    // The block starts on the line after the opening delimiter
+    let (mut frontmatter, extra): (FrontMatter, BTreeMap<String, Value>) = match format {
+        Format::Yaml => {
+            let yaml = || serde_yaml::Deserializer::from_str(block);
+            serde_path_to_error::deserialize(yaml())
+                .and_then(|x| Ok((x, serde_path_to_error::deserialize(yaml())?)))
+                .map_err(|err| {
+                    // Errors from within a field, like a bad timestamp, have no location of
+                    // their own, so they point at the key they are under
+                    let key = match err.path().iter().next() {
+                        Some(Segment::Map { key }) => key_location(block, key),
+                        _ => None,
+                    };
+                    let err = err.into_inner();
+                    let (line, column) = key
+                        .or_else(|| err.location().map(|x| (x.line(), x.column())))
+                        .map(|(line, column)| (line + 1, column))
+                        .unwrap_or((1, 1));
+                    error(line, column, strip_locations(&err.to_string()))
+                })
+        }
+        Format::Toml => toml::from_str(block)
+            .and_then(|x| {
+                let extra: BTreeMap<String, toml::Value> = toml::from_str(block)?;
+                Ok((
+                    x,
+                    extra.into_iter().map(|(k, v)| (k, from_toml(v))).collect(),
+                ))
+            })
+            .map_err(|err| {
+                let (line, column) = err
+                    .span()
+                    .map(|span| line_column(block, span.start))
+                    .map(|(line, column)| (line + 1, column))
+                    .unwrap_or((1, 1));
+                error(line, column, err.message().trim_end().to_string())
+            }),
+    }?;
+    frontmatter.extra = extra
+        .into_iter()
+        .filter(|(key, _)| !FIELDS.contains(&key.as_str()))
+        .collect();
+    Ok((frontmatter, body))
+}
+
+/// `value` as the YAML would be that templates see for the same frontmatter, with
+/// datetimes as strings
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(x) => Value::String(x),
+        toml::Value::Integer(x) => Value::Number(x.into()),
+        toml::Value::Float(x) => Value::Number(x.into()),
+        toml::Value::Boolean(x) => Value::Bool(x),
+        toml::Value::Datetime(x) => Value::String(x.to_string()),
+        toml::Value::Array(x) => Value::Sequence(x.into_iter().map(from_toml).collect()),
+        toml::Value::Table(x) => Value::Mapping(
+            x.into_iter()
+                .map(|(k, v)| (Value::String(k), from_toml(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// 1-based line and column of the value of the top level YAML key `key` in `block`
+fn key_location(block: &str, key: &str) -> Option<(usize, usize)> {
+    block.lines().enumerate().find_map(|(i, line)| {
+        let rest = line.strip_prefix(key)?.trim_start().strip_prefix(':')?;
+        let value = rest.trim_start();
+        Some((i + 1, line.len() - value.len() + 1))
+    })
+}
+
+/// Removes every ` at line 3 column 7` from a serde_yaml error message, which count
+/// from the start of the block rather than the file
+fn strip_locations(message: &str) -> String {
+    const AT: &str = " at line ";
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find(AT) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + AT.len()..];
+        let location = skip_number(after)
+            .and_then(|x| x.strip_prefix(" column "))
+            .and_then(skip_number);
+        match location {
+            Some(next) => rest = next,
+            None => {
+                out.push_str(AT);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// What follows the number `input` starts with, if it does
+fn skip_number(input: &str) -> Option<&str> {
+    let rest = input.trim_start_matches(|c: char| c.is_ascii_digit());
+    (rest.len() < input.len()).then_some(rest)
+}
+
+fn split_line(input: &str) -> (&str, &str) {
+    match input.split_once('\n') {
+        Some((line, rest)) => (line, rest),
+        None => (input, ""),
+    }
+}
+
+/// 1-based line and column of the byte `offset` in `input`
+pub fn line_column(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset.min(input.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTimestamp {
+    String(String),
+    Toml(toml::value::Datetime),
+}
+
+pub fn parse_timestamp(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc2822(value)
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .map_err(|_| format!("invalid timestamp `{value}`, expected RFC 2822 or RFC 3339"))
+}
+
+impl RawTimestamp {
+    fn parse<E: serde::de::Error>(self) -> Result<DateTime<FixedOffset>, E> {
+        let value = match self {
+            RawTimestamp::String(x) => x,
+            RawTimestamp::Toml(x) => x.to_string(),
+        };
+        parse_timestamp(value.trim()).map_err(E::custom)
+    }
+}
+
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<FixedOffset>, D::Error> {
+    RawTimestamp::deserialize(deserializer)?.parse()
+}
+
+fn deserialize_optional_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    Option::<RawTimestamp>::deserialize(deserializer)?
+        .map(RawTimestamp::parse)
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = parse("post.md", source).unwrap_err();
+        (err.line, err.column, err.reason)
+    }
+
+    #[test]
+    fn parses_yaml_and_toml() {
+        let (yaml, body) = parse(
+            "post.md",
+            "---\ntitle: A\ntimestamp: 2024-10-23T20:00:00Z\ntags: [a, b]\n---\nbody\n",
+        )
+        .unwrap();
+        assert_eq!(yaml.title, "A");
+        assert_eq!(yaml.tags, ["a", "b"]);
+        assert_eq!(body, "body\n");
+        let (toml, body) = parse(
+            "post.md",
+            "+++\ntitle = \"A\"\ntimestamp = 2024-10-23T20:00:00Z\n+++\nbody\n",
+        )
+        .unwrap();
+        assert_eq!(toml.timestamp, yaml.timestamp);
+        assert_eq!(body, "body\n");
+    }
+
+    #[test]
+    fn keeps_unknown_keys_as_extra() {
+        let (yaml, _) = parse(
+            "post.md",
+            "---\ntitle: A\ntimestamp: 2024-10-23T20:00:00Z\nmood: happy\n---\n",
+        )
+        .unwrap();
+        assert_eq!(yaml.extra.keys().collect::<Vec<_>>(), ["mood"]);
+        let (toml, _) = parse(
+            "post.md",
+            "+++\ntitle = \"A\"\ntimestamp = 2024-10-23T20:00:00Z\nat = 2024-10-24\n+++\n",
+        )
+        .unwrap();
+        assert_eq!(toml.extra["at"], Value::String("2024-10-24".to_string()));
+    }
+
+    #[test]
+    fn locates_yaml_errors_in_the_file() {
+        assert_eq!(
+            error("---\ntitle: A\ntimestamp: yesterday\n---\n"),
+            (
+                3,
+                12,
+                "invalid timestamp `yesterday`, expected RFC 2822 or RFC 3339".to_string()
+            )
+        );
+        let (line, column, reason) = error("---\ntitle: A\ntags: [a\n---\n");
+        assert_eq!((line, column), (3, 7), "{reason}");
+        assert!(!reason.contains(" at line "), "{reason}");
+    }
+
+    #[test]
+    fn locates_toml_errors_in_the_file() {
+        let (line, column, reason) = error("+++\ntitle = \"A\"\ntimestamp = \"yesterday\"\n+++\n");
+        assert_eq!((line, column), (3, 13), "{reason}");
+        assert!(reason.contains("invalid timestamp `yesterday`"), "{reason}");
+        let (line, column, reason) = error("+++\ntitle = \"A\"\ntimestamp =\n+++\n");
+        assert_eq!((line, column), (3, 12), "{reason}");
+    }
+
+    #[test]
+    fn rejects_missing_and_unterminated_blocks() {
+        assert_eq!(error("# A\n").0, 1);
+        let (_, _, reason) = error("---\ntitle: A\n");
+        assert_eq!(reason, "unterminated frontmatter, expected a closing `---`");
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        assert_eq!(line_column("ab\ncé d", 7), (2, 4));
+        assert_eq!(line_column("ab", 10), (1, 3));
+    }
+}
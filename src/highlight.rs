@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{LazyLock, Mutex},
+};
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+use trowel::html::{escape_attribute, escape_text};
+
+use crate::error::Error;
+
+/// Prefixed so the highlighting classes do not clash with the site's own
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The highlighting theme among the assets, see [`crate::assets::url`]
+pub const STYLESHEET: &str = "highlight.css";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Stylesheet for the classes the highlighter puts out, coloured like `theme`
+pub fn theme_css(theme: &str) -> Result<String, Error> {
+    let unknown = || Error::UnknownTheme {
+        name: theme.to_string(),
+        available: THEMES.themes.keys().cloned().collect(),
+    };
+    let css = THEMES
+        .themes
+        .get(theme)
+        .ok_or_else(unknown)
+        .and_then(|x| css_for_theme_with_class_style(x, CLASS_STYLE).map_err(|_| unknown()))?;
+    Ok(css + LINE_CSS)
+}
+
+/// Line numbers and highlighted lines are ours, not the theme's
+const LINE_CSS: &str = "
+.line-number {
+  display: inline-block;
+  min-width: 2em;
+  padding-right: 1em;
+  text-align: right;
+  opacity: 0.5;
+  user-select: none;
+}
+
+.line.highlighted {
+  display: inline-block;
+  width: 100%;
+  background-color: rgba(255, 255, 0, 0.2);
+}
+";
+
+/// Options given after the language in a fence's info string, e.g.
+/// ```` ```rust linenos linenostart=10 hl_lines=1,3-4 ````
+#[derive(Default)]
+struct FenceOptions {
+    line_numbers: bool,
+    first_line: usize,
+    /// Inclusive ranges of line numbers within the block, counted from 1 whatever
+    /// `first_line` is
+    highlighted: Vec<(usize, usize)>,
+}
+
+impl FenceOptions {
+    fn parse(info: &str) -> Self {
+        let mut options = FenceOptions {
+            first_line: 1,
+            ..Default::default()
+        };
+        for option in info.split_whitespace() {
+            match option.split_once('=') {
+                None if option == "linenos" => options.line_numbers = true,
+                Some(("linenostart", start)) => {
+                    options.first_line = start.parse().unwrap_or(1);
+                }
+                Some(("hl_lines", ranges)) => {
+                    for range in ranges.split(',') {
+                        let (start, end) = range.split_once('-').unwrap_or((range, range));
+                        if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse()) {
+                            options.highlighted.push((start, end));
+                        }
+                    }
+                }
+                // Meant for something else, e.g. a template
+                _ => {}
+            }
+        }
+        options
+    }
+
+    fn wraps_lines(&self) -> bool {
+        self.line_numbers || !self.highlighted.is_empty()
+    }
+
+    /// Whether the `line`th line of the block, counting from 1, is highlighted
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted
+            .iter()
+            .any(|(start, end)| (start..=end).contains(&&line))
+    }
+}
+
+/// Highlights fenced code for comrak with class names instead of inline styles,
+/// so the colours come from [`theme_css`]
+#[derive(Default)]
+pub struct Highlighter {
+    /// comrak hands the info string to [`Self::write_code_tag`] but not to
+    /// [`Self::write_highlighted`], which is called right after it
+    options: Mutex<Option<FenceOptions>>,
+}
+
+impl SyntaxHighlighterAdapter for Highlighter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        let options = self.options.lock().unwrap().take().unwrap_or_default();
+        let syntax = lang
+            .filter(|x| !x.is_empty())
+            .and_then(|x| SYNTAXES.find_syntax_by_token(x))
+            .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+        // Code syntect can not parse is still worth showing, just without colours
+        let html = highlight(syntax, code).unwrap_or_else(|_| escape_text(code).into_owned());
+        if !options.wraps_lines() {
+            return output.write_all(html.as_bytes());
+        }
+
+        for (i, line) in split_lines(&html).iter().enumerate() {
+            let number = options.first_line + i;
+            let class = if options.is_highlighted(i + 1) {
+                "line highlighted"
+            } else {
+                "line"
+            };
+            write!(output, "<span class=\"{class}\">")?;
+            if options.line_numbers {
+                write!(output, "<span class=\"line-number\">{number}</span>")?;
+            }
+            writeln!(output, "{line}</span>")?;
+        }
+        Ok(())
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        mut attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        let class = attributes.entry("class".to_string()).or_default();
+        class.insert_str(
+            0,
+            if class.is_empty() {
+                "highlight"
+            } else {
+                "highlight "
+            },
+        );
+        write_tag(output, "pre", &attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        mut attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        let meta = attributes.remove("data-meta").unwrap_or_default();
+        *self.options.lock().unwrap() = Some(FenceOptions::parse(&meta));
+        write_tag(output, "code", &attributes)
+    }
+}
+
+fn highlight(syntax: &SyntaxReference, code: &str) -> Result<String, syntect::Error> {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line)?;
+    }
+    Ok(generator.finalize())
+}
+
+fn write_tag(
+    output: &mut dyn Write,
+    tag: &str,
+    attributes: &HashMap<String, String>,
+) -> io::Result<()> {
+    // Sorted so the output does not change between builds
+    let mut attributes = attributes.iter().collect::<Vec<_>>();
+    attributes.sort();
+    write!(output, "<{tag}")?;
+    for (name, value) in attributes {
+        write!(
+            output,
+            " {}=\"{}\"",
+            escape_text(name),
+            escape_attribute(value)
+        )?;
+    }
+    write!(output, ">")
+}
+
+/// Splits highlighted html into lines, closing the spans still open at the end of
+/// each line and opening them again on the next, so every line can be wrapped on its own
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    // Whether `line` has anything besides tags
+    let mut has_text = false;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with("</span>") {
+            open.pop();
+            line.push_str("</span>");
+            rest = &rest["</span>".len()..];
+        } else if rest.starts_with("<span") {
+            let end = rest.find('>').map_or(rest.len(), |x| x + 1);
+            open.push(&rest[..end]);
+            line.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some(rest_of_line) = rest.strip_prefix('\n') {
+            line.push_str(&"</span>".repeat(open.len()));
+            lines.push(std::mem::take(&mut line));
+            line.extend(open.iter().copied());
+            has_text = false;
+            rest = rest_of_line;
+        } else {
+            let end = rest.find(['<', '\n']).unwrap_or(rest.len());
+            line.push_str(&rest[..end]);
+            has_text = true;
+            rest = &rest[end..];
+        }
+    }
+    if has_text {
+        line.push_str(&"</span>".repeat(open.len()));
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fence_options() {
+        let options = FenceOptions::parse("linenos linenostart=10 hl_lines=1,3-4 class=x");
+        assert!(options.line_numbers);
+        assert_eq!(options.first_line, 10);
+        assert_eq!(options.highlighted, [(1, 1), (3, 4)]);
+        assert!(options.wraps_lines());
+        let highlighted = (1..=5)
+            .filter(|x| options.is_highlighted(*x))
+            .collect::<Vec<_>>();
+        assert_eq!(highlighted, [1, 3, 4]);
+    }
+
+    #[test]
+    fn ignores_bad_fence_options() {
+        let options = FenceOptions::parse("linenostart=x hl_lines=a,2-b,,5");
+        assert!(!options.line_numbers);
+        assert_eq!(options.first_line, 1);
+        assert_eq!(options.highlighted, [(5, 5)]);
+        assert!(!FenceOptions::parse("").wraps_lines());
+    }
+
+    #[test]
+    fn counts_highlighted_lines_from_the_block() {
+        let options = FenceOptions::parse("linenostart=10 hl_lines=2");
+        assert!(options.is_highlighted(2));
+        assert!(!options.is_highlighted(11));
+    }
+
+    #[test]
+    fn splits_lines_inside_spans() {
+        assert_eq!(
+            split_lines("<span class=\"a\">x\ny</span>\nz\n"),
+            [
+                "<span class=\"a\">x</span>",
+                "<span class=\"a\">y</span>",
+                "z",
+            ]
+        );
+        assert_eq!(
+            split_lines("<span class=\"a\"><span class=\"b\">x\n</span>y</span>"),
+            [
+                "<span class=\"a\"><span class=\"b\">x</span></span>",
+                "<span class=\"a\"><span class=\"b\"></span>y</span>",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_empty_lines() {
+        assert_eq!(split_lines("a\n\nb\n"), ["a", "", "b"]);
+        assert!(split_lines("").is_empty());
+    }
+}
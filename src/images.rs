@@ -0,0 +1,696 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    metadata::Orientation,
+    DynamicImage, ImageDecoder, ImageReader, ImageResult,
+};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use trowel::html::escape_attribute;
+
+use crate::{
+    cache::{self, Cache},
+    config,
+    error::Error,
+    linkcheck, permalink,
+};
+
+/// Bump whenever the encoding changes, so cached images are made again
+const PIPELINE_VERSION: &str = "1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpeg,
+    Png,
+    /// Lossless, the encoder does not do lossy WebP, so it is only offered for PNGs
+    /// and WebP files are copied like any other static file
+    Webp,
+    Avif,
+}
+
+impl Format {
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "png" => Some(Format::Png),
+            _ => None,
+        }
+    }
+
+    /// Whether it is worth offering images in `source` in this format too
+    fn converts_from(self, source: Format) -> bool {
+        match self {
+            _ if self == source => false,
+            // Lossless copies of photos come out bigger than the photos
+            Format::Webp => source == Format::Png,
+            _ => true,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+            Format::Webp => "webp",
+            Format::Avif => "avif",
+        }
+    }
+
+    fn mime_type(self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Png => "image/png",
+            Format::Webp => "image/webp",
+            Format::Avif => "image/avif",
+        }
+    }
+}
+
+/// How the images in the static directory are resized and converted, set under
+/// `[images]` in the site config
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageOptions {
+    /// Widths to make smaller copies at, any at least as wide as the image are skipped
+    pub widths: Vec<u32>,
+    /// Formats to offer every image in besides its own, `webp` (for PNGs) and `avif`
+    pub formats: Vec<Format>,
+    /// For the lossy formats, from 1 to 100
+    pub quality: u8,
+    /// The `sizes` given along with `srcset`, how wide images are shown
+    pub sizes: Option<String>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            widths: vec![480, 960, 1920],
+            formats: vec![Format::Webp],
+            quality: 80,
+            sizes: None,
+        }
+    }
+}
+
+/// A copy of an image written to the output
+#[derive(Debug)]
+struct Variant {
+    output: String,
+    format: Format,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug)]
+struct Image {
+    src: PathBuf,
+    /// Hash of the source file
+    hash: String,
+    format: Format,
+    /// How the image is turned when shown, which survives its metadata being stripped
+    orientation: Orientation,
+    /// As shown, after the orientation it was taken in is applied
+    width: u32,
+    height: u32,
+    /// The image at its own path, in its own format, comes first
+    variants: Vec<Variant>,
+}
+
+/// The images in the static directory, which are written re-encoded without their
+/// metadata instead of copied, along with resized and converted variants
+#[derive(Debug, Default)]
+pub struct Images {
+    /// By output path, e.g. `static/photo.jpg`
+    images: BTreeMap<String, Image>,
+}
+
+impl Images {
+    /// Finds the images among `files`, sources and the outputs they are copied to,
+    /// reading no more of each than it takes to know its size, and only hashing those
+    /// `cache` does not know unchanged
+    ///
+    /// Files that do not decode are left to be copied like any other static file.
+    pub fn scan(files: &[(PathBuf, String)], cache: &Cache) -> (Self, Vec<Error>) {
+        let (images, errors) = files
+            .par_iter()
+            .filter_map(|(src, output)| {
+                let format = src
+                    .extension()
+                    .and_then(|x| x.to_str())
+                    .and_then(Format::from_extension)?;
+                Some((src, output, format))
+            })
+            .filter_map(|(src, output, format)| {
+                match Image::read(src.clone(), output, format, cache) {
+                    Ok(image) => Some(Ok((output.clone(), image))),
+                    Err(err @ Error::Image { .. }) => {
+                        eprintln!("warning: {err}, copying it as is");
+                        None
+                    }
+                    Err(err) => Some(Err(err)),
+                }
+            })
+            .partition::<Vec<_>, Vec<_>, _>(Result::is_ok);
+        let images = images
+            .into_iter()
+            .map(Result::unwrap)
+            .map(|(output, mut image)| {
+                image.variants = variants(&output, image.format, image.width, image.height);
+                (output, image)
+            })
+            .collect();
+        (
+            Images { images },
+            errors.into_iter().filter_map(Result::err).collect(),
+        )
+    }
+
+    /// Whether `output` is an image written by [`Self::write`] instead of copied
+    pub fn contains(&self, output: &str) -> bool {
+        self.images.contains_key(output)
+    }
+
+    /// Hash of everything pages need to know about the images
+    pub fn key(&self) -> String {
+        cache::hash(self.images.values().flat_map(|image| {
+            image
+                .variants
+                .iter()
+                .flat_map(|x| [x.output.clone(), x.width.to_string(), x.height.to_string()])
+        }))
+    }
+
+    /// Writes every variant that changed since the last build, returning the errors
+    /// for any that could not be
+    ///
+    /// The image itself is copied with its metadata stripped, only the other variants
+    /// are encoded.
+    pub fn write(&self, cache: &mut Cache, output_dir: &Path) -> Vec<Error> {
+        let quality = config::get().images.quality;
+        let mut errors = Vec::new();
+        for image in self.images.values() {
+            let result = cache.write_from(&image.src, &image.variants[0].output, |bytes| {
+                strip_metadata(image.format, image.orientation, bytes)
+            });
+            errors.extend(result.err());
+        }
+        let stale = self
+            .images
+            .values()
+            .filter_map(|image| {
+                let variants = image.variants[1..]
+                    .iter()
+                    .filter(|x| {
+                        let key = cache::hash([
+                            PIPELINE_VERSION,
+                            &image.hash,
+                            x.format.extension(),
+                            &x.width.to_string(),
+                            &quality.to_string(),
+                        ]);
+                        !cache.is_fresh(&x.output, key)
+                    })
+                    .collect::<Vec<_>>();
+                (!variants.is_empty()).then_some((image, variants))
+            })
+            .collect::<Vec<_>>();
+        // With the outputs that were not written because of each
+        let failed = stale
+            .into_par_iter()
+            .flat_map_iter(|(image, variants)| {
+                let decoded = match image.decode() {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        let err = Error::Image {
+                            path: image.src.clone(),
+                            reason: err.to_string(),
+                        };
+                        return vec![(variants, err, true)];
+                    }
+                };
+                variants
+                    .into_iter()
+                    .filter_map(|variant| {
+                        let result = write_variant(&decoded, image, variant, quality, output_dir);
+                        result.err().map(|err| (vec![variant], err, false))
+                    })
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        for (variants, err, undecodable) in failed {
+            // Make sure they are tried again next time
+            for variant in variants {
+                cache.invalidate(&variant.output);
+            }
+            if undecodable {
+                // The image itself is still there, as it was
+                eprintln!("warning: {err}, leaving out its other sizes and formats");
+            } else {
+                errors.push(err);
+            }
+        }
+        errors
+    }
+
+    /// Gives every `<img>` in `html` showing one of the images its size and a `srcset`
+    /// of the variants, wrapped in a `<picture>` offering the other formats
+    ///
+    /// Only root relative `src`s, like `/static/photo.jpg`, are recognised, and images
+    /// that already have a `srcset` are left alone.
+    pub fn rewrite(&self, html: &str) -> String {
+        if self.images.is_empty() {
+            return html.to_string();
+        }
+        let mut rewritten = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find("<img") {
+            rewritten.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(tag) = linkcheck::parse_tag(rest).filter(|x| x.name == "img") else {
+                rewritten.push_str("<img");
+                rest = &rest["<img".len()..];
+                continue;
+            };
+            let attribute = |name: &str| {
+                tag.attributes
+                    .iter()
+                    .find(|(x, _)| x == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            let image = attribute("src")
+                .filter(|_| attribute("srcset").is_none())
+                .and_then(|src| self.get(src));
+            match image {
+                Some(image) => image.write_picture(&mut rewritten, &tag.attributes),
+                None => rewritten.push_str(&rest[..tag.len]),
+            }
+            rest = &rest[tag.len..];
+        }
+        rewritten.push_str(rest);
+        rewritten
+    }
+
+    fn get(&self, src: &str) -> Option<&Image> {
+        let base_url = &config::get().base_url;
+        let src = src.strip_prefix(base_url.as_str()).unwrap_or(src);
+        let path = src.strip_prefix('/')?.split(['?', '#']).next()?;
+        self.images.get(&permalink::percent_decode(path))
+    }
+}
+
+impl Image {
+    fn read(src: PathBuf, output: &str, format: Format, cache: &Cache) -> Result<Self, Error> {
+        let header = || -> ImageResult<_> {
+            let mut decoder = ImageReader::open(&src)?
+                .with_guessed_format()?
+                .into_decoder()?;
+            Ok((decoder.dimensions(), decoder.orientation()?))
+        };
+        let ((width, height), orientation) = header().map_err(|err| Error::Image {
+            path: src.clone(),
+            reason: err.to_string(),
+        })?;
+        let (width, height) = match orientation {
+            Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH => (height, width),
+            _ => (width, height),
+        };
+        Ok(Image {
+            hash: cache.source_hash(&src, output)?,
+            src,
+            format,
+            orientation,
+            width,
+            height,
+            variants: Vec::new(),
+        })
+    }
+
+    /// The pixels, turned the way they are meant to be shown
+    fn decode(&self) -> ImageResult<DynamicImage> {
+        let mut decoder = ImageReader::open(&self.src)?
+            .with_guessed_format()?
+            .into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(image)
+    }
+
+    fn write_picture(&self, html: &mut String, attributes: &[(String, String)]) {
+        let options = &config::get().images;
+        let srcset = |format: Format| {
+            let mut variants = self
+                .variants
+                .iter()
+                .filter(|x| x.format == format)
+                .collect::<Vec<_>>();
+            variants.sort_by_key(|x| x.width);
+            variants
+                .iter()
+                .map(|x| format!("/{} {}w", x.output, x.width))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let sizes = options
+            .sizes
+            .as_ref()
+            .map(|x| format!(" sizes=\"{}\"", escape_attribute(x)))
+            .unwrap_or_default();
+        let formats = options
+            .formats
+            .iter()
+            .filter(|x| x.converts_from(self.format))
+            .collect::<Vec<_>>();
+        if !formats.is_empty() {
+            html.push_str("<picture>");
+        }
+        for format in &formats {
+            write!(
+                html,
+                "<source type=\"{}\" srcset=\"{}\"{sizes} />",
+                format.mime_type(),
+                escape_attribute(&srcset(**format))
+            )
+            .unwrap();
+        }
+        html.push_str("<img");
+        let has_size = attributes
+            .iter()
+            .any(|(x, _)| x == "width" || x == "height");
+        for (name, value) in attributes {
+            if name == "src" {
+                write!(
+                    html,
+                    " src=\"/{}\" srcset=\"{}\"{sizes}",
+                    escape_attribute(&self.variants[0].output),
+                    escape_attribute(&srcset(self.format))
+                )
+                .unwrap();
+            } else if name != "sizes" {
+                write!(html, " {name}=\"{}\"", escape_attribute(value)).unwrap();
+            }
+        }
+        // Lets the browser set aside the space before the image has loaded
+        if !has_size {
+            write!(html, " width=\"{}\" height=\"{}\"", self.width, self.height).unwrap();
+        }
+        html.push_str(" />");
+        if !formats.is_empty() {
+            html.push_str("</picture>");
+        }
+    }
+}
+
+/// The copies made of the `width` by `height` image at `output`, the image itself
+/// first, then the smaller ones and then the other formats
+///
+/// They are named after the whole of `output`, e.g. `photo-jpg-480w.webp`, so that
+/// `photo.jpg` and `photo.png` do not write over each other's.
+fn variants(output: &str, format: Format, width: u32, height: u32) -> Vec<Variant> {
+    let options = &config::get().images;
+    let stem = match output.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}-{extension}"),
+        None => output.to_string(),
+    };
+    let mut widths = options
+        .widths
+        .iter()
+        .copied()
+        .filter(|x| *x > 0 && *x < width)
+        .collect::<Vec<_>>();
+    widths.sort();
+    widths.dedup();
+    // Scaled to keep the aspect ratio, rounded to the nearest pixel
+    let variant = |format: Format, w: u32, output: String| Variant {
+        output,
+        format,
+        width: w,
+        height: ((height as u64 * w as u64 + width as u64 / 2) / width as u64).max(1) as u32,
+    };
+
+    let mut variants = vec![variant(format, width, output.to_string())];
+    for &x in &widths {
+        variants.push(variant(
+            format,
+            x,
+            format!("{stem}-{x}w.{}", format.extension()),
+        ));
+    }
+    for &other in options.formats.iter().filter(|x| x.converts_from(format)) {
+        for &x in widths.iter().chain([&width]) {
+            variants.push(variant(
+                other,
+                x,
+                format!("{stem}-{x}w.{}", other.extension()),
+            ));
+        }
+    }
+    variants
+}
+
+fn write_variant(
+    image: &DynamicImage,
+    source: &Image,
+    variant: &Variant,
+    quality: u8,
+    output_dir: &Path,
+) -> Result<(), Error> {
+    let resized;
+    let image = if variant.width == source.width {
+        image
+    } else {
+        resized = image.resize_exact(variant.width, variant.height, FilterType::Lanczos3);
+        &resized
+    };
+    let mut bytes = Vec::new();
+    // Nothing but the pixels is carried over, which leaves the metadata behind
+    let result = match variant.format {
+        Format::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality)),
+        Format::Png => image.write_with_encoder(PngEncoder::new(&mut bytes)),
+        Format::Webp => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut bytes)),
+        Format::Avif => image
+            .to_rgba8()
+            .write_with_encoder(AvifEncoder::new_with_speed_quality(&mut bytes, 8, quality)),
+    };
+    result.map_err(|err| Error::Image {
+        path: source.src.clone(),
+        reason: err.to_string(),
+    })?;
+    let path = output_dir.join(&variant.output);
+    cache::create_parent(&path)?;
+    std::fs::write(&path, bytes).map_err(Error::io("write", path))
+}
+
+/// `bytes` of an image in `format` without the metadata that can say where and by
+/// what it was taken, leaving the pixels as they are
+///
+/// What is dropped is EXIF, XMP, IPTC and comments. An image that has to be turned to
+/// be shown the right way up keeps an EXIF orientation, the only one in JPEGs and the
+/// whole chunk in PNGs. Anything that does not parse is left alone.
+fn strip_metadata(format: Format, orientation: Orientation, bytes: Vec<u8>) -> Vec<u8> {
+    let turned = orientation != Orientation::NoTransforms;
+    let stripped = match format {
+        Format::Jpeg => strip_jpeg(&bytes, turned.then(|| orientation.to_exif())),
+        Format::Png => strip_png(&bytes, turned),
+        Format::Webp | Format::Avif => None,
+    };
+    stripped.unwrap_or(bytes)
+}
+
+fn strip_jpeg(bytes: &[u8], orientation: Option<u8>) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut out = bytes[..2].to_vec();
+    if let Some(orientation) = orientation {
+        // APP1 with a big endian TIFF header and an IFD of just the orientation
+        out.extend_from_slice(&[0xff, 0xe1, 0x00, 0x22]);
+        out.extend_from_slice(b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01");
+        out.extend_from_slice(&[0, orientation, 0, 0, 0, 0, 0, 0]);
+    }
+    let mut i = 2;
+    loop {
+        if *bytes.get(i)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(i + 1)?;
+        if marker == 0xff {
+            // Fill byte
+            i += 1;
+            continue;
+        }
+        if marker == 0xda {
+            // Start of scan, nothing but image data and the end after it
+            out.extend_from_slice(&bytes[i..]);
+            return Some(out);
+        }
+        let len = if matches!(marker, 0x01 | 0xd0..=0xd7) {
+            2
+        } else {
+            2 + u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize
+        };
+        let segment = bytes.get(i..i + len)?;
+        // APP1 is EXIF and XMP, APP13 IPTC and COM comments
+        if !matches!(marker, 0xe1 | 0xed | 0xfe) {
+            out.extend_from_slice(segment);
+        }
+        i += len;
+    }
+}
+
+fn strip_png(bytes: &[u8], keep_exif: bool) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut out = SIGNATURE.to_vec();
+    let mut i = SIGNATURE.len();
+    while i < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC
+        let chunk = bytes.get(i..i + 12 + len)?;
+        let kind = &chunk[4..8];
+        let metadata = matches!(kind, b"tEXt" | b"zTXt" | b"iTXt" | b"tIME")
+            || (kind == b"eXIf" && !keep_exif);
+        if !metadata {
+            out.extend_from_slice(chunk);
+        }
+        i += chunk.len();
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::RgbImage;
+
+    use super::*;
+
+    fn outputs(output: &str, format: Format, width: u32, height: u32) -> Vec<String> {
+        variants(output, format, width, height)
+            .into_iter()
+            .map(|x| format!("{} {}x{}", x.output, x.width, x.height))
+            .collect()
+    }
+
+    fn encoded(format: Format) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 2));
+        let mut bytes = Vec::new();
+        match format {
+            Format::Jpeg => image.write_with_encoder(JpegEncoder::new(&mut bytes)),
+            _ => image.write_with_encoder(PngEncoder::new(&mut bytes)),
+        }
+        .unwrap();
+        bytes
+    }
+
+    /// `bytes` with `segment` put in right after the JPEG's start of image marker
+    fn with_segment(bytes: &[u8], marker: u8, data: &[u8]) -> Vec<u8> {
+        let len = (data.len() as u16 + 2).to_be_bytes();
+        [&bytes[..2], &[0xff, marker], &len, data, &bytes[2..]].concat()
+    }
+
+    fn decoded_orientation(bytes: &[u8]) -> Orientation {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap()
+            .orientation()
+            .unwrap()
+    }
+
+    #[test]
+    fn only_processes_jpegs_and_pngs() {
+        assert_eq!(Format::from_extension("JPG"), Some(Format::Jpeg));
+        assert_eq!(Format::from_extension("png"), Some(Format::Png));
+        // The encoder would turn lossy WebP into much bigger lossless WebP
+        assert_eq!(Format::from_extension("webp"), None);
+        assert_eq!(Format::from_extension("gif"), None);
+    }
+
+    #[test]
+    fn offers_smaller_sizes_and_other_formats() {
+        assert_eq!(
+            outputs("static/a.png", Format::Png, 1000, 500),
+            [
+                "static/a.png 1000x500",
+                "static/a-png-480w.png 480x240",
+                "static/a-png-960w.png 960x480",
+                "static/a-png-480w.webp 480x240",
+                "static/a-png-960w.webp 960x480",
+                "static/a-png-1000w.webp 1000x500",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_photos_in_their_own_format() {
+        assert_eq!(
+            outputs("a.jpg", Format::Jpeg, 500, 333),
+            ["a.jpg 500x333", "a-jpg-480w.jpg 480x320"]
+        );
+    }
+
+    #[test]
+    fn does_not_scale_small_images_up() {
+        assert_eq!(outputs("a.jpg", Format::Jpeg, 480, 1), ["a.jpg 480x1"]);
+        assert_eq!(outputs("a", Format::Jpeg, 3, 1000), ["a 3x1000"]);
+    }
+
+    #[test]
+    fn strips_jpeg_metadata() {
+        let original = encoded(Format::Jpeg);
+        let tagged = with_segment(&original, 0xfe, b"taken at home");
+        let tagged = with_segment(&tagged, 0xe1, b"Exif\0\0MM\0\x2a\0\0\0\x08\0\0\0\0\0\0");
+        let stripped = strip_metadata(Format::Jpeg, Orientation::NoTransforms, tagged);
+        assert_eq!(stripped, original);
+    }
+
+    #[test]
+    fn keeps_the_orientation_of_jpegs() {
+        let original = encoded(Format::Jpeg);
+        let stripped = strip_metadata(Format::Jpeg, Orientation::Rotate90, original.clone());
+        assert_eq!(decoded_orientation(&stripped), Orientation::Rotate90);
+        assert_eq!(stripped.len(), original.len() + 36);
+        assert_eq!(decoded_orientation(&original), Orientation::NoTransforms);
+    }
+
+    #[test]
+    fn strips_png_text() {
+        let original = encoded(Format::Png);
+        // After the signature and the 25 byte IHDR chunk
+        let at = 8 + 25;
+        let text = b"\0\0\0\x07tEXtA\0where\0\0\0\0";
+        let tagged = [&original[..at], &text[..], &original[at..]].concat();
+        let stripped = strip_metadata(Format::Png, Orientation::NoTransforms, tagged);
+        assert_eq!(stripped, original);
+    }
+
+    #[test]
+    fn leaves_what_it_cannot_parse_alone() {
+        for format in [Format::Jpeg, Format::Png] {
+            let truncated = encoded(format)[..20].to_vec();
+            let stripped = strip_metadata(format, Orientation::NoTransforms, truncated.clone());
+            assert_eq!(stripped, truncated);
+        }
+        let stripped = strip_metadata(Format::Jpeg, Orientation::NoTransforms, b"GIF".to_vec());
+        assert_eq!(stripped, b"GIF");
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{config, error::Error, files, permalink};
+
+/// A link found in a page of the output
+struct Link {
+    line: usize,
+    url: String,
+}
+
+/// What [`scan`] finds in a page
+#[derive(Default)]
+struct Scanned {
+    links: Vec<Link>,
+    /// Everything that can be linked to with a `#fragment`
+    anchors: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct Report {
+    /// The pages that were checked
+    pub pages: usize,
+    pub links: usize,
+    /// Links that do not go to a page or file in the output, or to an anchor that is not there
+    pub broken: Vec<Error>,
+    /// Links off the site, which are not checked
+    pub external: BTreeSet<String>,
+}
+
+/// Checks every link in the HTML files in `output_dir` against the files around it,
+/// `sources` maps outputs to the page they were rendered from, for the report
+pub fn check(output_dir: &Path, sources: &HashMap<PathBuf, PathBuf>) -> Result<Report, Error> {
+    let (files, errors) = files::walk(output_dir, "");
+    if let Some(err) = errors.into_iter().next() {
+        return Err(err);
+    }
+    let scanned = files
+        .into_par_iter()
+        .map(|(path, _)| path)
+        .filter(|path| path.extension().is_some_and(|x| x == "html" || x == "htm"))
+        .map(|path| {
+            let html = std::fs::read_to_string(&path).map_err(Error::io("read", &path))?;
+            Ok((path, scan(&html)))
+        })
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+    let base_url = &config::get().base_url;
+    let mut report = Report {
+        pages: scanned.len(),
+        ..Default::default()
+    };
+    let mut files = scanned.keys().collect::<Vec<_>>();
+    files.sort();
+    for file in files {
+        let relative = file.strip_prefix(output_dir).unwrap_or(file);
+        let dir_url = match relative.parent() {
+            Some(parent) if parent != Path::new("") => {
+                format!("/{}/", parent.to_string_lossy().replace('\\', "/"))
+            }
+            _ => "/".to_string(),
+        };
+        for link in &scanned[file].links {
+            report.links += 1;
+            let url = link.url.trim();
+            // Links back to the site itself are checked like any other
+            let url = match url.strip_prefix(base_url.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with(['/', '?', '#']) => rest,
+                _ => url,
+            };
+            if is_external(url) {
+                report.external.insert(url.to_string());
+                continue;
+            }
+            let (url, fragment) = url.split_once('#').unwrap_or((url, ""));
+            let path = url.split('?').next().unwrap_or("");
+            let path = if path.is_empty() {
+                format!("/{}", relative.to_string_lossy().replace('\\', "/"))
+            } else {
+                join(&dir_url, &permalink::percent_decode(path))
+            };
+            let broken = |reason: String| Error::BrokenLink {
+                file: file.clone(),
+                line: link.line,
+                page: sources.get(relative).cloned(),
+                reason,
+            };
+            let Some(target) = permalink::resolve_exact(output_dir, &path) else {
+                report
+                    .broken
+                    .push(broken(format!("broken link to {}", link.url)));
+                continue;
+            };
+            if fragment.is_empty() {
+                continue;
+            }
+            // Only HTML has anchors, anything else is up to whatever opens it
+            if let Some(target) = scanned.get(&target) {
+                if !target
+                    .anchors
+                    .contains(&permalink::percent_decode(fragment))
+                {
+                    report
+                        .broken
+                        .push(broken(format!("missing anchor in link to {}", link.url)));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Whether `url` has a scheme or host of its own, or is not a link to a file at all
+fn is_external(url: &str) -> bool {
+    if url.starts_with("//") {
+        return true;
+    }
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || matches!(x, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Resolves `path` against the directory URL `dir`, like a browser would
+fn join(dir: &str, path: &str) -> String {
+    let joined = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{dir}{path}")
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in joined.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    let trailing = joined.ends_with('/') || joined.ends_with("/.") || joined.ends_with("/..");
+    let mut url = format!("/{}", segments.join("/"));
+    if trailing && !segments.is_empty() {
+        url.push('/');
+    }
+    url
+}
+
+/// Finds the links and anchors in `html`, which is assumed to be mostly well formed
+fn scan(html: &str) -> Scanned {
+    let mut scanned = Scanned::default();
+    let mut rest = html;
+    let mut line = 1;
+    fn advance(rest: &mut &str, by: usize, line: &mut usize) {
+        *line += rest[..by].matches('\n').count();
+        *rest = &rest[by..];
+    }
+    while let Some(start) = rest.find('<') {
+        advance(&mut rest, start, &mut line);
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |x| x + 3);
+            advance(&mut rest, end, &mut line);
+            continue;
+        }
+        let Some(tag) = parse_tag(rest) else {
+            advance(&mut rest, 1, &mut line);
+            continue;
+        };
+        for (name, value) in &tag.attributes {
+            match name.as_str() {
+                "href" | "src" => scanned.links.push(Link {
+                    line,
+                    url: value.clone(),
+                }),
+                "srcset" => scanned.links.extend(
+                    value
+                        .split(',')
+                        .filter_map(|x| x.split_whitespace().next())
+                        .map(|url| Link {
+                            line,
+                            url: url.to_string(),
+                        }),
+                ),
+                "id" => {
+                    scanned.anchors.insert(value.clone());
+                }
+                "name" if tag.name == "a" => {
+                    scanned.anchors.insert(value.clone());
+                }
+                _ => {}
+            }
+        }
+        advance(&mut rest, tag.len, &mut line);
+        // Their contents are not markup
+        if matches!(tag.name.as_str(), "script" | "style") {
+            let end = rest.find(&format!("</{}", tag.name)).unwrap_or(rest.len());
+            advance(&mut rest, end, &mut line);
+        }
+    }
+    scanned
+}
+
+pub struct Tag {
+    pub name: String,
+    /// In source order, with entities decoded
+    pub attributes: Vec<(String, String)>,
+    /// Length of the tag in the source
+    pub len: usize,
+}
+
+/// Parses the start tag `input` begins with, with its names lowercased and its
+/// attribute values unescaped
+pub fn parse_tag(input: &str) -> Option<Tag> {
+    let (tag, len) = trowel::parse_tag(input)?;
+    let trowel::Tag::Start {
+        name, attributes, ..
+    } = tag
+    else {
+        return None;
+    };
+    Some(Tag {
+        name: name.to_ascii_lowercase(),
+        attributes: attributes
+            .into_iter()
+            .map(|(name, value)| {
+                let value = value.map(decode_entities).unwrap_or_default();
+                (name.to_ascii_lowercase(), value)
+            })
+            .collect(),
+        len,
+    })
+}
+
+/// Undoes the character references attribute values are escaped with
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|x| *x <= 10) else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|x| u32::from_str_radix(x, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|x| x.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_start_tags() {
+        let tag = parse_tag("<A HREF='/a?b=1&amp;c=2' download>text").unwrap();
+        assert_eq!(tag.name, "a");
+        assert_eq!(
+            tag.attributes,
+            [
+                ("href".to_string(), "/a?b=1&c=2".to_string()),
+                ("download".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(tag.len, "<A HREF='/a?b=1&amp;c=2' download>".len());
+        let tag = parse_tag("<img src=/x.png alt=\"a > b\"/>").unwrap();
+        assert_eq!(tag.attributes[1].1, "a > b");
+        assert_eq!(tag.len, "<img src=/x.png alt=\"a > b\"/>".len());
+    }
+
+    #[test]
+    fn only_parses_start_tags() {
+        assert!(parse_tag("</a>").is_none());
+        assert!(parse_tag("< a>").is_none());
+        assert!(parse_tag("<a href='unterminated>").is_none());
+    }
+
+    #[test]
+    fn decodes_character_references() {
+        assert_eq!(decode_entities("&lt;&#65;&#x42;&quot;"), "<AB\"");
+        assert_eq!(decode_entities("a & b &bogus; &"), "a & b &bogus; &");
+    }
+
+    #[test]
+    fn joins_paths_like_a_browser() {
+        assert_eq!(join("/a/b/", "c.html"), "/a/b/c.html");
+        assert_eq!(join("/a/b/", "../c/"), "/a/c/");
+        assert_eq!(join("/a/b/", "/c"), "/c");
+        assert_eq!(join("/a/", "../../.."), "/");
+        assert_eq!(join("/a/", "./"), "/a/");
+        assert_eq!(join("/a/", ".."), "/");
+    }
+
+    #[test]
+    fn tells_external_links_apart() {
+        for url in [
+            "https://example.com",
+            "//cdn.example.com/x",
+            "mailto:a@b.c",
+            "a:b",
+        ] {
+            assert!(is_external(url), "{url}");
+        }
+        for url in ["/a.html", "a.html", "#top", "?q=1", ":a", "a b:c"] {
+            assert!(!is_external(url), "{url}");
+        }
+    }
+
+    #[test]
+    fn finds_links_and_anchors() {
+        let scanned = scan(
+            "<h1 id=top>a</h1>\n<!-- <a href=/hidden> -->\n<a name=old href=\"#top\">b</a>\n\
+             <img srcset=\"/a.png 1x, /b.png 2x\">\n<script>if (a <b) {}</script><a href=/c>",
+        );
+        let links = scanned
+            .links
+            .iter()
+            .map(|x| (x.line, x.url.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [(3, "#top"), (4, "/a.png"), (4, "/b.png"), (5, "/c")]
+        );
+        let mut anchors = scanned.anchors.iter().collect::<Vec<_>>();
+        anchors.sort();
+        assert_eq!(anchors, ["old", "top"]);
+    }
+}
@@ -1,67 +1,165 @@
 use std::{
-    io::{BufRead, Write},
-    path::Path,
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, OnceLock},
 };
 
-use blog::BlogPageBase;
+use build::BuildOptions;
 use chrono::{DateTime, FixedOffset};
 use clap::{Parser, Subcommand};
-use comrak::Options;
-use trowel::{trowel_html, util::html_to_string};
+use config::Config;
+use error::Error;
+use images::Images;
+use markdown::{MarkdownOptions, Rendered};
+use toc::TocEntry;
+use xref::Target;
+mod assets;
 mod blog;
-
+mod build;
+mod cache;
+mod config;
+mod error;
+mod feed;
+mod files;
+mod frontmatter;
+mod highlight;
+mod images;
+mod linkcheck;
+mod markdown;
+mod permalink;
+mod serve;
+mod sitemap;
+mod taxonomy;
+mod template;
+mod toc;
+mod xref;
 
 #[derive(Debug)]
 struct Page {
+    /// The markdown file the page was loaded from
+    path: PathBuf,
     title: String,
     timestamp: DateTime<FixedOffset>,
-    slug: String,
-    body: String,
+    /// Root-relative URL the page is published at
+    url: String,
+    markdown: String,
+    /// Line of the source file the markdown starts on, minus one
+    markdown_line: usize,
+    /// Overrides the site's markdown options for this page
+    markdown_options: MarkdownOptions,
+    /// Whether a table of contents goes above the body
+    show_toc: bool,
+    rendered: OnceLock<Rendered>,
+    /// Cross references in the markdown, as written, and the pages they resolve to
+    references: BTreeMap<String, Target>,
+    /// Pages with a cross reference to this one, newest first
+    backlinks: Vec<Target>,
+    /// The site's images, which the body's `<img>` tags are pointed at
+    images: Arc<Images>,
+    /// Hash of the whole source file
+    source_hash: String,
+    /// Frontmatter fields nakssg does not know about, for templates
+    extra: BTreeMap<String, frontmatter::Value>,
+    tags: Vec<String>,
+    categories: Vec<String>,
+    draft: bool,
+    noindex: bool,
+    updated: Option<DateTime<FixedOffset>>,
+    publish_date: Option<DateTime<FixedOffset>>,
+    expiry_date: Option<DateTime<FixedOffset>>,
 }
 
 impl Page {
-    fn load(path: impl AsRef<Path>) -> Self {
+    fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
         let path = path.as_ref();
-        let file = std::fs::File::open(path).expect("Could not find file");
-        let reader = std::io::BufReader::new(file);
-        let mut lines = reader.lines();
-        let mut title = None;
-        let mut timestamp = None;
-        {
-            assert!(lines.next().unwrap().unwrap() == "---");
-            for line in lines.by_ref() {
-                let line = line.unwrap();
-                if line == "---" {
-                    break;
-                }
-                let (key, value) = line.split_once(":").unwrap();
-                match key {
-                    "title" => {
-                        title = Some(value.trim().to_string());
-                    }
-                    "timestamp" => {
-                        timestamp = Some(chrono::DateTime::parse_from_rfc2822(value.trim()))
-                    }
-                    _ => unreachable!(),
-                }
+        let source = std::fs::read_to_string(path).map_err(Error::io("read", path))?;
+        Self::parse(path, &source)
+    }
+
+    /// The page `source` is the markdown of, as if loaded from `path`
+    fn parse(path: &Path, source: &str) -> Result<Self, Error> {
+        let (frontmatter, remaining) = frontmatter::parse(path, source)?;
+        let slug = match frontmatter.slug {
+            Some(slug) => slug,
+            None => path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .ok_or_else(|| Error::NonUtf8Path(path.to_path_buf()))?
+                .to_string(),
+        };
+        let pattern = frontmatter
+            .permalink
+            .as_deref()
+            .unwrap_or(&config::get().permalink);
+        let url = permalink::expand(pattern, &slug, frontmatter.timestamp).map_err(|reason| {
+            Error::Permalink {
+                path: path.to_path_buf(),
+                reason,
             }
-            assert!(title.is_some());
-            assert!(timestamp.is_some());
-        }
-        let slug = path.file_stem().unwrap();
-        let remaining = lines.map(|x| x.unwrap() + "\n").collect::<String>();
-        let mut options = Options::default();
-        options.extension.table = true;
-        options.extension.underline = true;
-        options.extension.greentext = true;
-        options.render.unsafe_ = true; // Allow html
-        let body = comrak::markdown_to_html(&remaining, &options);
-        Self {
-            title: title.unwrap(),
-            timestamp: timestamp.unwrap().unwrap(),
-            slug: slug.to_str().unwrap().to_string(),
-            body,
-        }
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            url,
+            title: frontmatter.title,
+            timestamp: frontmatter.timestamp,
+            markdown: remaining.to_string(),
+            markdown_line: source[..source.len() - remaining.len()]
+                .matches('\n')
+                .count(),
+            markdown_options: frontmatter.markdown,
+            show_toc: frontmatter.toc,
+            rendered: OnceLock::new(),
+            references: BTreeMap::new(),
+            backlinks: Vec::new(),
+            images: Arc::default(),
+            source_hash: cache::hash([source]),
+            extra: frontmatter.extra,
+            tags: frontmatter.tags,
+            categories: frontmatter.categories,
+            draft: frontmatter.draft,
+            noindex: frontmatter.noindex,
+            updated: frontmatter.updated,
+            publish_date: frontmatter.publish_date,
+            expiry_date: frontmatter.expiry_date,
+        })
+    }
+
+    /// The site's markdown options with the page's own on top
+    fn markdown_options(&self) -> MarkdownOptions {
+        config::get().markdown.merge(&self.markdown_options)
+    }
+
+    /// The markdown, only rendered when first needed
+    fn rendered(&self) -> &Rendered {
+        self.rendered.get_or_init(|| {
+            let mut rendered =
+                markdown::render(&self.markdown, &self.markdown_options(), &self.references);
+            rendered.html = self.images.rewrite(&rendered.html);
+            rendered
+        })
+    }
+
+    fn body(&self) -> &str {
+        &self.rendered().html
+    }
+
+    /// The page's headings nested as configured in the site config
+    fn toc(&self) -> Vec<TocEntry> {
+        toc::nest(&self.rendered().headings, &config::get().toc)
+    }
+
+    /// When the page last changed
+    fn lastmod(&self) -> DateTime<FixedOffset> {
+        self.updated.unwrap_or(self.timestamp)
+    }
+
+    /// Whether the page belongs in a build made at `now`
+    fn is_published(&self, now: DateTime<FixedOffset>, options: &BuildOptions) -> bool {
+        (options.drafts || !self.draft)
+            && (options.future || self.publish_date.is_none_or(|x| x <= now))
+            && self.expiry_date.is_none_or(|x| x > now)
     }
 }
 
@@ -69,6 +167,15 @@ impl Page {
 struct Command {
     #[arg(short, long, default_value = "pages")]
     input_dir: String,
+    /// Site layouts, the built in theme is used for any that are missing
+    #[arg(short, long, default_value = "templates")]
+    templates_dir: String,
+    /// Site config, the defaults are used if it does not exist
+    #[arg(short, long, default_value = "nakssg.toml")]
+    config: String,
+    /// Number of threads used to render pages, defaults to one per core
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
     #[command(subcommand)]
     command: Subcommands,
 }
@@ -77,101 +184,124 @@ struct Command {
 enum Subcommands {
     Build {
         #[arg(short, long, default_value = "dist")]
-        output_dir: String
+        output_dir: String,
+        /// Overrides the `base_url` in the site config
+        #[arg(long)]
+        base_url: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
     },
     New {
         slug_name: String,
+        /// Create the post ready to publish instead of as a draft
+        #[arg(long)]
+        publish: bool,
+    },
+    /// Prints the stylesheet for highlighted code, to customise it
+    HighlightCss {
+        /// Defaults to `highlight_theme` from the site config
+        #[arg(long)]
+        theme: Option<String>,
+    },
+    /// Builds the site and serves it on localhost, rebuilding on changes
+    Serve {
+        /// Apart from where `build` writes, as everything in it links to localhost
+        #[arg(short, long, default_value = ".nakssg-serve")]
+        output_dir: String,
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+        #[command(flatten)]
+        options: BuildOptions,
     },
 }
 
 impl Command {
-    fn run(self) {
+    fn run(self) -> Result<(), Vec<Error>> {
+        let mut config = Config::load(&self.config).map_err(|err| vec![err])?;
+        match &self.command {
+            Subcommands::Build {
+                base_url: Some(base_url),
+                ..
+            } => config.base_url = base_url.trim_end_matches('/').to_string(),
+            Subcommands::Serve { port, .. } => config.base_url = serve::base_url(*port),
+            _ => {}
+        }
+        config::set(config);
+        if let Some(jobs) = self.jobs {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs)
+                .build_global()
+                .map_err(|err| vec![Error::Threads(err)])?;
+        }
+
         match self.command {
-            Subcommands::New { slug_name } => {
+            Subcommands::New { slug_name, publish } => {
                 let path = Path::new(&self.input_dir)
                     .join(&slug_name)
                     .with_extension("md");
-                assert!(!path.exists(), "Already exists!");
-                let file = std::fs::File::create(path).expect("Could not create file");
-                let mut writer = std::io::BufWriter::new(file);
-                writeln!(writer, "---").unwrap();
-                writeln!(writer, "title: {}", slug_name).unwrap();
-                writeln!(writer, "timestamp: {}", chrono::Utc::now().to_rfc2822()).unwrap();
-                writeln!(writer, "---").unwrap();
+                new_post(&path, &slug_name, publish).map_err(|err| vec![err])
             }
-            Subcommands::Build { output_dir } => {
-                let output_dir = Path::new(&output_dir);
-                std::fs::create_dir_all(output_dir).unwrap();
-                let input_dir = Path::new(&self.input_dir);
-                let static_dir = input_dir.join("static");
-                let output_static_dir = output_dir.join("static");
-                std::fs::create_dir_all(&static_dir).unwrap();
-                std::fs::create_dir_all(&output_static_dir).unwrap();
-                let pages = std::fs::read_dir(input_dir)
-                    .unwrap()
-                    .map(|x| x.unwrap().path())
-                    .filter(|x| x.is_file() && x.extension().unwrap() == "md")
-                    .map(Page::load)
-                    .collect::<Vec<_>>();
-
-                let pages2 = &pages;
-                let index = html_to_string(trowel_html! {
-                    !{let pages = pages2;},
-                    !BlogPageBase(title: {Some("Blog")}) {
-                        ul(style: "list-style-type:none;") {
-                            {
-                                pages.iter().map(|page| {
-                                    Box::new(trowel_html!(
-                                        li {
-                                            a(href: {Some(format!("/{}", page.slug))}) {
-                                                {page.title.as_str()},
-                                                sub {
-                                                    !{let timestamp = page.timestamp.to_rfc2822();},
-                                                    time(datetime: {Some(&timestamp)}) {
-                                                        {timestamp}
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    )) as Box<dyn Fn(&mut dyn trowel::HtmlWriter)>
-                                }).collect::<Vec<Box<dyn Fn(&mut dyn trowel::HtmlWriter)>>>()
-                            }
-                        }
-                    }
-                });
-                std::fs::write(output_dir.join("index.html"), index).unwrap();
-
-                for page in pages {
-                    let html = html_to_string(trowel_html! {
-                        !BlogPageBase(title: {Some(&page.title)}, timestamp: {Some(page.timestamp.to_rfc2822())}) {
-                            {page.body.as_str()}
-                        }
-                    });
-                    std::fs::write(output_dir.join(page.slug).with_extension("html"), html)
-                        .unwrap();
-                }
-
-                copy_static_content(static_dir, output_static_dir);
+            Subcommands::HighlightCss { theme } => {
+                let theme = theme.as_ref().unwrap_or(&config::get().highlight_theme);
+                print!("{}", highlight::theme_css(theme).map_err(|err| vec![err])?);
+                Ok(())
             }
+            Subcommands::Build {
+                output_dir,
+                base_url: _,
+                options,
+            } => build::build(
+                Path::new(&self.input_dir),
+                Path::new(&self.templates_dir),
+                Path::new(&output_dir),
+                &options,
+            ),
+            Subcommands::Serve {
+                output_dir,
+                port,
+                options,
+            } => serve::serve(
+                Path::new(&self.input_dir),
+                Path::new(&self.templates_dir),
+                Path::new(&self.config),
+                Path::new(&output_dir),
+                port,
+                options,
+            )
+            .map_err(|err| vec![err]),
         }
     }
 }
 
-fn copy_static_content(src: impl AsRef<Path>, dst: impl AsRef<Path>) {
-    for entry in std::fs::read_dir(src).unwrap() {
-        let entry = entry.unwrap();
-        let src_path = entry.path();
-        let dst_path = dst.as_ref().join(src_path.file_name().unwrap());
-        if src_path.is_dir() {
-            std::fs::create_dir_all(&dst_path).unwrap();
-            copy_static_content(&src_path, &dst_path);
-        } else {
-            std::fs::copy(&src_path, &dst_path).unwrap();
+/// Creates an empty post at `path`, as a draft unless `publish` is set
+fn new_post(path: &Path, title: &str, publish: bool) -> Result<(), Error> {
+    let file = std::fs::File::create_new(path).map_err(|err| match err.kind() {
+        std::io::ErrorKind::AlreadyExists => Error::AlreadyExists(path.to_path_buf()),
+        _ => Error::io("create", path)(err),
+    })?;
+    // Quoted as YAML needs it, a title like `Rust: a retrospective` would not parse otherwise
+    let title = serde_yaml::to_string(title).expect("a string is always valid YAML");
+    let mut writer = std::io::BufWriter::new(file);
+    let mut write = || -> std::io::Result<()> {
+        writeln!(writer, "---")?;
+        write!(writer, "title: {title}")?;
+        writeln!(writer, "timestamp: {}", chrono::Utc::now().to_rfc2822())?;
+        if !publish {
+            writeln!(writer, "draft: true")?;
         }
-    }
+        writeln!(writer, "---")?;
+        writer.flush()
+    };
+    write().map_err(Error::io("write", path))
 }
 
-fn main() {
+fn main() -> ExitCode {
     let command = Command::parse();
-    command.run();
+    match command.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(errors) => {
+            error::report(&errors);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use comrak::{nodes::NodeValue, Arena, Options, Plugins};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    highlight::Highlighter,
+    toc::{Heading, HeadingCollector},
+    xref::{Target, CROSS_REFERENCE},
+};
+
+/// Which comrak extensions are turned on, set for the whole site under `[markdown]`
+/// in the site config and per page under `markdown` in its frontmatter
+///
+/// Anything left out falls back to the site config and then to the defaults, which are
+/// comrak's except that `table`, `underline`, `greentext` and wiki links are on. Raw HTML is
+/// dropped from the output unless `unsafe` is turned on.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarkdownOptions {
+    pub strikethrough: Option<bool>,
+    pub tagfilter: Option<bool>,
+    pub table: Option<bool>,
+    pub autolink: Option<bool>,
+    pub tasklist: Option<bool>,
+    pub superscript: Option<bool>,
+    /// Prefix for the ids given to headings
+    pub header_ids: Option<String>,
+    pub footnotes: Option<bool>,
+    pub description_lists: Option<bool>,
+    pub front_matter_delimiter: Option<String>,
+    pub multiline_block_quotes: Option<bool>,
+    pub math_dollars: Option<bool>,
+    pub math_code: Option<bool>,
+    pub wikilinks_title_after_pipe: Option<bool>,
+    pub wikilinks_title_before_pipe: Option<bool>,
+    pub underline: Option<bool>,
+    pub spoiler: Option<bool>,
+    pub greentext: Option<bool>,
+    /// Curly quotes, dashes and ellipses
+    pub smart: Option<bool>,
+    /// Every newline in a paragraph becomes a `<br>`
+    pub hardbreaks: Option<bool>,
+    /// Let raw HTML in the markdown through to the page
+    #[serde(rename = "unsafe")]
+    pub unsafe_: Option<bool>,
+}
+
+impl MarkdownOptions {
+    /// These options with everything `overrides` sets replaced
+    pub fn merge(&self, overrides: &MarkdownOptions) -> MarkdownOptions {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                MarkdownOptions {
+                    $($field: overrides.$field.clone().or_else(|| self.$field.clone()),)*
+                }
+            };
+        }
+        merge!(
+            strikethrough,
+            tagfilter,
+            table,
+            autolink,
+            tasklist,
+            superscript,
+            header_ids,
+            footnotes,
+            description_lists,
+            front_matter_delimiter,
+            multiline_block_quotes,
+            math_dollars,
+            math_code,
+            wikilinks_title_after_pipe,
+            wikilinks_title_before_pipe,
+            underline,
+            spoiler,
+            greentext,
+            smart,
+            hardbreaks,
+            unsafe_
+        )
+    }
+
+    pub fn comrak_options(&self) -> Options<'static> {
+        let mut options = Options::default();
+        let extension = &mut options.extension;
+        extension.strikethrough = self.strikethrough.unwrap_or(false);
+        extension.tagfilter = self.tagfilter.unwrap_or(false);
+        extension.table = self.table.unwrap_or(true);
+        extension.autolink = self.autolink.unwrap_or(false);
+        extension.tasklist = self.tasklist.unwrap_or(false);
+        extension.superscript = self.superscript.unwrap_or(false);
+        extension.header_ids = self.header_ids.clone();
+        extension.footnotes = self.footnotes.unwrap_or(false);
+        extension.description_lists = self.description_lists.unwrap_or(false);
+        extension.front_matter_delimiter = self.front_matter_delimiter.clone();
+        extension.multiline_block_quotes = self.multiline_block_quotes.unwrap_or(false);
+        extension.math_dollars = self.math_dollars.unwrap_or(false);
+        extension.math_code = self.math_code.unwrap_or(false);
+        // Wiki links are how pages refer to each other, so they are on unless set up otherwise
+        extension.wikilinks_title_after_pipe = self
+            .wikilinks_title_after_pipe
+            .unwrap_or(self.wikilinks_title_before_pipe != Some(true));
+        extension.wikilinks_title_before_pipe = self.wikilinks_title_before_pipe.unwrap_or(false);
+        extension.underline = self.underline.unwrap_or(true);
+        extension.spoiler = self.spoiler.unwrap_or(false);
+        extension.greentext = self.greentext.unwrap_or(true);
+        options.parse.smart = self.smart.unwrap_or(false);
+        options.render.hardbreaks = self.hardbreaks.unwrap_or(false);
+        options.render.unsafe_ = self.unsafe_.unwrap_or(false);
+        // Hands the fence options to the highlighter
+        options.render.full_info_string = true;
+        options
+    }
+}
+
+#[derive(Debug)]
+pub struct Rendered {
+    pub html: String,
+    pub headings: Vec<Heading>,
+}
+
+/// Cross references in `markdown`, `[[name]]` wiki links and `[text](@/name.md)` links,
+/// as written and with the line they are on
+pub fn references(markdown: &str, options: &MarkdownOptions) -> Vec<(String, usize)> {
+    let arena = Arena::new();
+    let root = comrak::parse_document(&arena, markdown, &options.comrak_options());
+    root.descendants()
+        .filter_map(|node| {
+            let ast = node.data.borrow();
+            let url = match &ast.value {
+                NodeValue::WikiLink(link) => &link.url,
+                NodeValue::Link(link) if link.url.starts_with(CROSS_REFERENCE) => &link.url,
+                _ => return None,
+            };
+            Some((url.clone(), ast.sourcepos.start.line))
+        })
+        .collect()
+}
+
+/// Renders `markdown` to HTML with fenced code highlighted, every heading given an id
+/// and cross references pointed at the pages in `references`
+pub fn render(
+    markdown: &str,
+    options: &MarkdownOptions,
+    references: &BTreeMap<String, Target>,
+) -> Rendered {
+    let options = options.comrak_options();
+    let arena = Arena::new();
+    let root = comrak::parse_document(&arena, markdown, &options);
+    for node in root.descendants() {
+        let mut ast = node.data.borrow_mut();
+        let (url, wiki) = match &mut ast.value {
+            NodeValue::WikiLink(link) => (&mut link.url, true),
+            NodeValue::Link(link) if link.url.starts_with(CROSS_REFERENCE) => {
+                (&mut link.url, false)
+            }
+            _ => continue,
+        };
+        let Some(target) = references.get(url.as_str()) else {
+            continue;
+        };
+        let written = std::mem::replace(url, target.url.clone());
+        drop(ast);
+        // A bare `[[name]]` shows the title of the page instead of its name
+        if let Some(child) = node.first_child().filter(|_| wiki) {
+            if let NodeValue::Text(text) = &mut child.data.borrow_mut().value {
+                if *text == written {
+                    *text = target.title.clone();
+                }
+            }
+        }
+    }
+
+    let highlighter = Highlighter::default();
+    let headings = HeadingCollector::new(options.extension.header_ids.clone().unwrap_or_default());
+    let mut plugins = Plugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&highlighter);
+    plugins.render.heading_adapter = Some(&headings);
+    let mut html = Vec::new();
+    comrak::format_html_with_plugins(root, &options, &mut html, &plugins)
+        .expect("Writing to a Vec can not fail");
+    Rendered {
+        html: String::from_utf8(html).expect("comrak writes UTF-8"),
+        headings: headings.into_headings(),
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Datelike, FixedOffset};
+
+/// Fills in the placeholders of a permalink `pattern` such as `/:year/:month/:slug/`,
+/// understands `:year`, `:month`, `:day` and `:slug`
+pub fn expand(
+    pattern: &str,
+    slug: &str,
+    timestamp: DateTime<FixedOffset>,
+) -> Result<String, String> {
+    fill(pattern, |name| match name {
+        "year" => Some(format!("{:04}", timestamp.year())),
+        "month" => Some(format!("{:02}", timestamp.month())),
+        "day" => Some(format!("{:02}", timestamp.day())),
+        "slug" => Some(slug.to_string()),
+        _ => None,
+    })
+}
+
+/// Fills in the placeholders of `pattern` with what `value` gives for them
+///
+/// Fails on a placeholder `value` does not know, and on a URL that would be written
+/// outside of the output directory.
+pub fn fill(pattern: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut url = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find(':') {
+        url.push_str(&rest[..start]);
+        let name_len = rest[start + 1..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len() - start - 1);
+        let name = &rest[start + 1..start + 1 + name_len];
+        match value(name) {
+            Some(value) => url.push_str(&value),
+            None if name.is_empty() => url.push(':'),
+            None => return Err(format!("unknown placeholder :{name} in {pattern}")),
+        }
+        rest = &rest[start + 1 + name_len..];
+    }
+    url.push_str(rest);
+    // Placeholder names are letters only, so any dot is the pattern's own
+    let is_file = pattern.rsplit('/').next().is_some_and(|x| x.contains('.'));
+    let url = normalize(&url, is_file);
+    if Path::new(&output_path(&url))
+        .components()
+        .any(|x| !matches!(x, Component::Normal(_)))
+    {
+        return Err(format!("{url} is outside of the output directory"));
+    }
+    Ok(url)
+}
+
+/// Checks that `pattern` only uses the `placeholders` and stays in the output directory
+pub fn check(pattern: &str, placeholders: &[&str]) -> Result<(), String> {
+    fill(pattern, |name| {
+        placeholders.contains(&name).then(|| name.to_string())
+    })
+    .map(|_| ())
+}
+
+/// Makes `url` root-relative, and gives it a trailing slash unless `is_file`,
+/// so every URL maps to exactly one output file
+pub fn normalize(url: &str, is_file: bool) -> String {
+    let mut url = format!("/{}", url.trim_start_matches('/'));
+    while url.contains("//") {
+        url = url.replace("//", "/");
+    }
+    if !is_file && !url.ends_with('/') {
+        url.push('/');
+    }
+    url
+}
+
+/// The file a root-relative `url` is written to, relative to the output directory
+pub fn output_path(url: &str) -> String {
+    let path = url.trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        format!("{path}index.html")
+    } else {
+        path.to_string()
+    }
+}
+
+/// Maps a request path to a file in `output_dir`, refusing anything outside of it
+///
+/// Like a static host would, `/about` is also served from `about.html` when there is
+/// no `about/index.html`.
+pub fn resolve(output_dir: &Path, path: &str) -> Option<PathBuf> {
+    resolve_exact(output_dir, path).or_else(|| {
+        let file = output_dir.join(path.trim_start_matches('/'));
+        if file.extension().is_some() {
+            return None;
+        }
+        Some(file.with_extension("html")).filter(|x| x.is_file())
+    })
+}
+
+/// Like [`resolve`], but only to the file `path` names or the `index.html` in it,
+/// which every host serves
+pub fn resolve_exact(output_dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|x| !matches!(x, Component::Normal(_)))
+    {
+        return None;
+    }
+    let file = output_dir.join(relative);
+    if file.is_dir() {
+        Some(file.join("index.html")).filter(|x| x.is_file())
+    } else {
+        Some(file).filter(|x| x.is_file())
+    }
+}
+
+/// Decodes `%xx` escapes, leaving anything malformed alone
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_at(pattern: &str, slug: &str) -> Result<String, String> {
+        let timestamp = DateTime::parse_from_rfc3339("2024-03-09T20:00:00+01:00").unwrap();
+        expand(pattern, slug, timestamp)
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(expand_at("/:slug.html", "post").unwrap(), "/post.html");
+        assert_eq!(
+            expand_at("/:year/:month/:day/:slug/", "post").unwrap(),
+            "/2024/03/09/post/"
+        );
+        assert_eq!(expand_at("blog/:slug", "post").unwrap(), "/blog/post/");
+    }
+
+    #[test]
+    fn decides_files_from_the_pattern() {
+        // A slug with a dot in it is still a directory
+        assert_eq!(expand_at("/:slug/", "v1.2").unwrap(), "/v1.2/");
+        assert_eq!(expand_at("/:slug", "v1.2").unwrap(), "/v1.2/");
+        assert_eq!(expand_at("/:slug.html", "v1.2").unwrap(), "/v1.2.html");
+        assert_eq!(
+            expand_at("/posts.:slug.html", "a").unwrap(),
+            "/posts.a.html"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        assert_eq!(
+            expand_at("/:title/", "post").unwrap_err(),
+            "unknown placeholder :title in /:title/"
+        );
+        assert!(expand_at("/../:slug.html", "post").is_err());
+        assert!(expand_at("/:slug.html", "..").is_ok());
+        assert!(expand_at("/:slug/", "..").is_err());
+        assert!(check("/:taxonomy/:slug.html", &["taxonomy", "slug"]).is_ok());
+        assert!(check("/:page/", &["taxonomy"]).is_err());
+    }
+
+    #[test]
+    fn keeps_colons_that_are_not_placeholders() {
+        assert_eq!(expand_at("/a:/:slug/", "b").unwrap(), "/a:/b/");
+    }
+
+    #[test]
+    fn normalizes_urls() {
+        assert_eq!(normalize("a//b", false), "/a/b/");
+        assert_eq!(normalize("//a.xml", true), "/a.xml");
+        assert_eq!(normalize("/", false), "/");
+    }
+
+    #[test]
+    fn maps_urls_to_files() {
+        assert_eq!(output_path("/"), "index.html");
+        assert_eq!(output_path("/a/b/"), "a/b/index.html");
+        assert_eq!(output_path("/a.html"), "a.html");
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("caf%C3%A9%20au%20lait"), "café au lait");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::Path,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use notify::{RecursiveMode, Watcher};
+
+use crate::{
+    build::{self, BuildOptions},
+    config::{self, Config},
+    error::{self, Error},
+    permalink,
+};
+
+const LIVE_RELOAD_PATH: &str = "/__nakssg/livereload";
+
+const LIVE_RELOAD_SNIPPET: &str = r#"<script>new EventSource("/__nakssg/livereload").onmessage = () => location.reload();</script>"#;
+
+/// Bumped after every rebuild so open pages know to reload
+#[derive(Default)]
+struct Generation {
+    count: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Generation {
+    fn bump(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+}
+
+/// The `base_url` pages are built with while served on `port`
+pub fn base_url(port: u16) -> String {
+    format!("http://127.0.0.1:{port}")
+}
+
+/// Builds the site, then serves `output_dir` on localhost and rebuilds whenever
+/// something in `input_dir` or `templates_dir`, or the config at `config_path`, changes
+pub fn serve(
+    input_dir: &Path,
+    templates_dir: &Path,
+    config_path: &Path,
+    output_dir: &Path,
+    port: u16,
+    options: BuildOptions,
+) -> Result<(), Error> {
+    // Still worth serving whatever could be built, the errors may be fixed while watching
+    if let Err(errors) = build::build(input_dir, templates_dir, output_dir, &options) {
+        error::report(&errors);
+    }
+
+    let generation = Arc::new(Generation::default());
+    {
+        let input_dir = input_dir.to_path_buf();
+        let templates_dir = templates_dir.to_path_buf();
+        let config_path = config_path.to_path_buf();
+        let output_dir = output_dir.to_path_buf();
+        let generation = generation.clone();
+        std::thread::spawn(move || {
+            let result = watch(
+                &input_dir,
+                &templates_dir,
+                &config_path,
+                port,
+                &output_dir,
+                &options,
+                &generation,
+            );
+            if let Err(err) = result {
+                error::report(&[err]);
+            }
+        });
+    }
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|source| Error::Listen { port, source })?;
+    println!(
+        "Serving {} on http://127.0.0.1:{port}",
+        output_dir.display()
+    );
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let output_dir = output_dir.to_path_buf();
+        let generation = generation.clone();
+        std::thread::spawn(move || {
+            // The browser going away mid response is not interesting
+            let _ = handle(stream, &output_dir, &generation);
+        });
+    }
+    Ok(())
+}
+
+fn watch(
+    input_dir: &Path,
+    templates_dir: &Path,
+    config_path: &Path,
+    port: u16,
+    output_dir: &Path,
+    options: &BuildOptions,
+    generation: &Generation,
+) -> Result<(), Error> {
+    // Watch absolute paths so the reported paths can be compared against them
+    let input_dir = std::fs::canonicalize(input_dir).map_err(Error::io("read", input_dir))?;
+    let output_dir = std::fs::canonicalize(output_dir).map_err(Error::io("read", output_dir))?;
+    let templates = std::fs::canonicalize(templates_dir).ok();
+    // Editors save by replacing the file, which only its directory sees
+    let config_path = std::path::absolute(config_path).map_err(Error::io("read", config_path))?;
+    let config_dir = config_path.parent().unwrap_or(Path::new("/"));
+    let (tx, rx) = mpsc::channel();
+    let watch_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| Error::Watch { path, source }
+    };
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error(&input_dir))?;
+    watcher
+        .watch(&input_dir, RecursiveMode::Recursive)
+        .map_err(watch_error(&input_dir))?;
+    // Templates are optional, a directory created later is only picked up on restart
+    if let Some(templates) = &templates {
+        watcher
+            .watch(templates, RecursiveMode::Recursive)
+            .map_err(watch_error(templates))?;
+    }
+    watcher
+        .watch(config_dir, RecursiveMode::NonRecursive)
+        .map_err(watch_error(config_dir))?;
+    let relevant = |path: &Path| {
+        path == config_path
+            || (path.starts_with(&input_dir)
+                || templates.as_ref().is_some_and(|x| path.starts_with(x)))
+                && !path.starts_with(&output_dir)
+    };
+
+    while let Ok(event) = rx.recv() {
+        let mut changed = Vec::new();
+        let mut event = Some(event);
+        // Editors tend to touch a file several times per save, wait for things to settle
+        while let Some(next) = event {
+            if let Ok(next) = next {
+                if !next.kind.is_access() {
+                    changed.extend(next.paths.into_iter().filter(|x| relevant(x)));
+                }
+            }
+            event = rx.recv_timeout(Duration::from_millis(100)).ok();
+        }
+        if changed.is_empty() {
+            continue;
+        }
+        if changed.contains(&config_path) {
+            match Config::load(&config_path) {
+                Ok(mut config) => {
+                    config.base_url = base_url(port);
+                    config::set(config);
+                }
+                Err(err) => {
+                    error::report(&[err]);
+                    eprintln!("Build failed, waiting for changes");
+                    continue;
+                }
+            }
+        }
+
+        let result = std::panic::catch_unwind(|| {
+            build::build(&input_dir, templates_dir, &output_dir, options)
+        });
+        match result {
+            Ok(Ok(())) => generation.bump(),
+            Ok(Err(errors)) => {
+                error::report(&errors);
+                eprintln!("Build failed, waiting for changes");
+            }
+            Err(_) => eprintln!("Build failed, waiting for changes"),
+        }
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, output_dir: &Path, generation: &Generation) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, nothing in them matters here
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(stream, "400 Bad Request", "text/plain", b"Bad request");
+    };
+    if method != "GET" && method != "HEAD" {
+        return respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method not allowed",
+        );
+    }
+    let path = permalink::percent_decode(target.split(['?', '#']).next().unwrap_or("/"));
+    if path == LIVE_RELOAD_PATH {
+        return live_reload(stream, generation);
+    }
+
+    let Some(file) = permalink::resolve(output_dir, &path) else {
+        return respond(stream, "404 Not Found", "text/plain", b"Not found");
+    };
+    let mut body = std::fs::read(&file)?;
+    let content_type = content_type(&file);
+    if content_type.starts_with("text/html") {
+        body = inject_live_reload(&String::from_utf8_lossy(&body)).into_bytes();
+    }
+    if method == "HEAD" {
+        body.clear();
+    }
+    respond(stream, "200 OK", content_type, &body)
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Holds the connection open as a server-sent event stream,
+/// sending an event every time the site is rebuilt
+fn live_reload(mut stream: TcpStream, generation: &Generation) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+    let mut seen = *generation.count.lock().unwrap();
+    loop {
+        let count = *generation
+            .changed
+            .wait_timeout_while(
+                generation.count.lock().unwrap(),
+                Duration::from_secs(15),
+                |x| *x == seen,
+            )
+            .unwrap()
+            .0;
+        let message: &[u8] = if count != seen {
+            seen = count;
+            b"data: reload\n\n"
+        } else {
+            // Lets us notice closed connections
+            b": ping\n\n"
+        };
+        stream.write_all(message)?;
+        stream.flush()?;
+    }
+}
+
+fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(i) => format!("{}{LIVE_RELOAD_SNIPPET}{}", &html[..i], &html[i..]),
+        None => format!("{html}{LIVE_RELOAD_SNIPPET}"),
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use trowel::{html, trowel_html, util::xml_to_string};
+
+use crate::{
+    blog::{index_page_count, pagination_url},
+    config::Config,
+    taxonomy::Taxonomy,
+    Page,
+};
+
+/// What goes in `robots.txt`
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RobotsOptions {
+    /// Paths crawlers are asked to stay out of
+    pub disallow: Vec<String>,
+    /// Added to the end as is, e.g. rules for specific crawlers
+    pub extra: String,
+}
+
+/// Every page of the site meant for search engines, with when it last changed
+///
+/// Drafts and pages marked `noindex` are left out, listings are as new as the
+/// newest page on them.
+pub fn sitemap(
+    pages: &[Page],
+    taxonomies: &[Taxonomy; 2],
+    config: &Config,
+) -> html::Result<String> {
+    let newest = |indices: &[usize]| indices.iter().map(|x| pages[*x].lastmod()).max();
+    let mut urls: Vec<(String, Option<DateTime<FixedOffset>>)> = Vec::new();
+    let lastmod = pages.iter().map(Page::lastmod).max();
+    for n in 1..=index_page_count(pages.len()) {
+        urls.push((pagination_url(n), lastmod));
+    }
+    urls.extend(
+        pages
+            .iter()
+            .filter(|x| !x.draft && !x.noindex)
+            .map(|x| (x.url.clone(), Some(x.lastmod()))),
+    );
+    for taxonomy in taxonomies {
+        urls.push((taxonomy.index_url(), lastmod));
+        urls.extend(
+            taxonomy
+                .terms
+                .values()
+                .map(|term| (taxonomy.term_url(&term.slug), newest(&term.pages))),
+        );
+    }
+
+    xml_to_string(trowel_html! {
+        r#"<?xml version="1.0" encoding="utf-8"?>"#,
+        urlset(xmlns: "http://www.sitemaps.org/schemas/sitemap/0.9") {
+            {
+                urls.iter().map(|(url, lastmod)| {
+                    let url = config.absolute_url(url);
+                    trowel_html! { move
+                        url {
+                            loc { {url.as_str()} },
+                            {lastmod.map(|x| trowel_html! { move
+                                lastmod { {x.to_rfc3339()} }
+                            })}
+                        }
+                    }
+                }).collect::<Vec<_>>()
+            }
+        }
+    })
+}
+
+/// `robots.txt` pointing crawlers at the sitemap
+pub fn robots(config: &Config) -> String {
+    let mut robots = "User-agent: *\n".to_string();
+    if config.robots.disallow.is_empty() {
+        robots.push_str("Allow: /\n");
+    }
+    for path in &config.robots.disallow {
+        writeln!(robots, "Disallow: {path}").unwrap();
+    }
+    writeln!(robots, "\nSitemap: {}", config.absolute_url("/sitemap.xml")).unwrap();
+    if !config.robots.extra.is_empty() {
+        writeln!(robots, "\n{}", config.robots.extra.trim_end()).unwrap();
+    }
+    robots
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+
+use trowel::{html, trowel_html, HtmlWriter, ToHtml};
+
+use crate::{config, error::Error, permalink, Page};
+
+pub struct Term {
+    pub name: String,
+    pub slug: String,
+    /// Indices into the page list, newest first
+    pub pages: Vec<usize>,
+}
+
+pub struct Taxonomy {
+    /// Used as the output directory, e.g. `tags`
+    pub name: &'static str,
+    pub title: &'static str,
+    pub terms: BTreeMap<String, Term>,
+}
+
+impl Taxonomy {
+    /// Gathers the terms of every page, along with an error for each term that
+    /// differs from another one only to end up at the same URL
+    pub fn collect(
+        name: &'static str,
+        title: &'static str,
+        pages: &[Page],
+        terms_of: impl Fn(&Page) -> &[String],
+    ) -> (Self, Vec<Error>) {
+        let mut terms = BTreeMap::<String, Term>::new();
+        let mut errors = Vec::new();
+        for (i, page) in pages.iter().enumerate() {
+            for term in terms_of(page) {
+                let slug = slugify(term);
+                if slug.is_empty() {
+                    continue;
+                }
+                let entry = terms.entry(slug.clone()).or_insert_with(|| Term {
+                    name: term.trim().to_string(),
+                    slug,
+                    pages: Vec::new(),
+                });
+                if entry.name.to_lowercase() != term.trim().to_lowercase() {
+                    errors.push(Error::TermCollision {
+                        path: page.path.clone(),
+                        taxonomy: name,
+                        name: term.trim().to_string(),
+                        other: entry.name.clone(),
+                    });
+                    continue;
+                }
+                if !entry.pages.contains(&i) {
+                    entry.pages.push(i);
+                }
+            }
+        }
+        for term in terms.values_mut() {
+            term.pages
+                .sort_by(|a, b| pages[*b].timestamp.cmp(&pages[*a].timestamp));
+        }
+        (Taxonomy { name, title, terms }, errors)
+    }
+
+    pub fn index_url(&self) -> String {
+        permalink::fill(&config::get().taxonomy_permalink, |name| {
+            (name == "taxonomy").then(|| self.name.to_string())
+        })
+        .expect("checked when the config was loaded")
+    }
+
+    /// URL of the term with `slug`, which is [`Term::slug`] or what [`slugify`] makes
+    /// of a name
+    pub fn term_url(&self, slug: &str) -> String {
+        permalink::fill(&config::get().term_permalink, |name| match name {
+            "taxonomy" => Some(self.name.to_string()),
+            "slug" => Some(slug.to_string()),
+            _ => None,
+        })
+        .expect("checked when the config was loaded")
+    }
+
+    /// Links to each of `terms`, for use on a page
+    pub fn links<'a>(
+        &'a self,
+        terms: &'a [String],
+    ) -> impl Fn(&mut dyn HtmlWriter) -> html::Result + 'a {
+        move |writer: &mut dyn HtmlWriter| {
+            if terms.is_empty() {
+                return Ok(());
+            }
+            (trowel_html! {
+                nav(class: {Some(self.name)}) {
+                    {format!("{}: ", self.title)},
+                    {
+                        terms.iter().map(|term| {
+                            trowel_html! { move
+                                a(href: {Some(self.term_url(&slugify(term)))}, rel: "tag") {
+                                    {term.as_str()}
+                                }
+                            }
+                        }).collect::<Vec<_>>()
+                    }
+                }
+            })
+            .to_html(writer)
+        }
+    }
+}
+
+/// Lowercases `term`, spells out the symbols that tell apart names like `C`, `C++`
+/// and `C#`, and replaces anything else that is not alphanumeric with dashes
+pub fn slugify(term: &str) -> String {
+    let mut slug = String::with_capacity(term.len());
+    for c in term.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+            continue;
+        }
+        if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        let word = match c {
+            '+' => "plus",
+            '#' => "sharp",
+            '&' => "and",
+            _ => continue,
+        };
+        slug.push_str(word);
+        slug.push('-');
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn page(name: &str, timestamp: &str, tags: &str) -> Page {
+        let source = format!("---\ntitle: {name}\ntimestamp: {timestamp}\ntags: [{tags}]\n---\n");
+        Page::parse(Path::new(&format!("{name}.md")), &source).unwrap()
+    }
+
+    fn collect(pages: &[Page]) -> (Taxonomy, Vec<Error>) {
+        Taxonomy::collect("tags", "Tags", pages, |x| &x.tags)
+    }
+
+    #[test]
+    fn slugifies_terms() {
+        assert_eq!(slugify("Rust"), "rust");
+        assert_eq!(slugify("  Web Dev  "), "web-dev");
+        assert_eq!(slugify("C++"), "c-plus-plus");
+        assert_eq!(slugify("C#"), "c-sharp");
+        assert_eq!(slugify("R&D"), "r-and-d");
+        assert_eq!(slugify("Ünïcode, too!"), "ünïcode-too");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn slugs_are_stable() {
+        for term in ["C++", "web-dev", "a  b", "Ünïcode"] {
+            assert_eq!(slugify(&slugify(term)), slugify(term));
+        }
+    }
+
+    #[test]
+    fn groups_pages_by_term_newest_first() {
+        let pages = [
+            page("a", "2024-01-01T00:00:00Z", "Rust, c"),
+            page("b", "2024-02-01T00:00:00Z", "rust"),
+            page("c", "2024-03-01T00:00:00Z", "C, C++, '?'"),
+        ];
+        let (tags, errors) = collect(&pages);
+        assert!(errors.is_empty(), "{errors:?}");
+        let terms = tags
+            .terms
+            .values()
+            .map(|x| (x.name.as_str(), x.slug.as_str(), x.pages.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            terms,
+            [
+                ("c", "c", vec![2, 0]),
+                ("C++", "c-plus-plus", vec![2]),
+                ("Rust", "rust", vec![1, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_terms_that_collide() {
+        let pages = [
+            page("a", "2024-01-01T00:00:00Z", "web dev"),
+            page("b", "2024-02-01T00:00:00Z", "web-dev"),
+        ];
+        let (tags, errors) = collect(&pages);
+        assert_eq!(tags.terms["web-dev"].pages, [0]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            Error::TermCollision { name, other, .. } if name == "web-dev" && other == "web dev"
+        ));
+    }
+
+    #[test]
+    fn links_terms_by_slug() {
+        let (tags, _) = collect(&[]);
+        assert_eq!(tags.term_url("c-plus-plus"), "/tags/c-plus-plus.html");
+        assert_eq!(tags.index_url(), "/tags/index.html");
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use minijinja::{
+    escape_formatter, path_loader, AutoEscape, Environment, ErrorKind, Output, State, Value,
+};
+use serde::Serialize;
+use trowel::{
+    html::{self, escape_attribute, IoWriter, WriteHtml},
+    minify::MinifyHtml,
+    pretty::PrettyHtml,
+    HtmlWriter,
+};
+
+use crate::{
+    assets, cache,
+    error::Error,
+    files, frontmatter,
+    taxonomy::{slugify, Taxonomy},
+    toc::TocEntry,
+    xref::Target,
+    Page,
+};
+
+/// Layouts supplied by the site in its templates directory, any layout that is
+/// missing is rendered with the built in [`crate::blog::BlogPageBase`] instead
+///
+/// Templates are looked up by name:
+/// - `page.html` for each post, with `page`
+/// - `index.html` for each page of the index, with `paginator`
+/// - `taxonomy.html` for the list of tags or categories, with `taxonomy`
+/// - `term.html` for the posts of a single tag or category, with `taxonomy` and `term`
+///
+/// and all of them get `site`, the site config, and `pages`, every published post
+/// newest first. Templates can extend or include anything else in the directory, and
+/// `asset("style.css")` gives the fingerprinted URL of a stylesheet or script.
+pub struct Templates {
+    env: Option<Environment<'static>>,
+    dir: PathBuf,
+    key: String,
+    /// How everything rendered is written out
+    style: HtmlStyle,
+}
+
+/// How the HTML of rendered pages is written out
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HtmlStyle {
+    /// As the layout has it
+    #[default]
+    AsIs,
+    /// As small as it goes, for serving
+    Minified,
+    /// Indented one element per line, for debugging layouts
+    Pretty,
+}
+
+impl HtmlStyle {
+    /// Writes what `html` writes to `writer` in this style, as it goes
+    fn write(
+        self,
+        writer: &mut impl Write,
+        html: impl FnOnce(&mut dyn HtmlWriter) -> html::Result,
+    ) -> html::Result {
+        match self {
+            HtmlStyle::AsIs => html(&mut WriteHtml::new(writer)),
+            HtmlStyle::Minified => {
+                let mut writer = MinifyHtml::new(writer);
+                html(&mut writer)?;
+                writer.finish().map(drop)
+            }
+            HtmlStyle::Pretty => {
+                let mut writer = PrettyHtml::new(writer);
+                html(&mut writer)?;
+                writer.finish().map(drop)
+            }
+        }
+    }
+}
+
+impl Templates {
+    /// Sets up the templates in `dir`, along with an error for each file in it that
+    /// could not be read
+    pub fn load(dir: &Path, style: HtmlStyle) -> (Self, Vec<Error>) {
+        if !dir.is_dir() {
+            let templates = Templates {
+                env: None,
+                dir: dir.to_path_buf(),
+                key: String::new(),
+                style,
+            };
+            return (templates, Vec::new());
+        }
+        let (files, mut errors) = files::walk(dir, "");
+        let mut contents = Vec::with_capacity(files.len() * 2);
+        for (path, _) in &files {
+            match std::fs::read(path) {
+                Ok(bytes) => {
+                    contents.push(path.to_string_lossy().into_owned().into_bytes());
+                    contents.push(bytes);
+                }
+                Err(err) => errors.push(Error::io("read", path)(err)),
+            }
+        }
+        let key = cache::hash(contents);
+
+        let mut env = Environment::new();
+        env.set_loader(path_loader(dir));
+        env.set_formatter(formatter);
+        env.add_function("asset", |name: &str| assets::url(name));
+        let templates = Templates {
+            env: Some(env),
+            dir: dir.to_path_buf(),
+            key,
+            style,
+        };
+        (templates, errors)
+    }
+
+    /// Hash of every file in the templates directory, empty if there is none
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.env.is_none()
+    }
+
+    /// Renders the template `name` for `output` with `context`, or writes `fallback` if
+    /// the site does not have one
+    pub fn render(
+        &self,
+        name: &str,
+        output: &str,
+        context: Value,
+        fallback: impl FnOnce(&mut dyn HtmlWriter) -> html::Result,
+    ) -> Result<String, Error> {
+        let html = self.render_template(name, output, context)?;
+        let mut buf = Vec::new();
+        self.write(output, Path::new(output), html, &mut buf, fallback)?;
+        Ok(String::from_utf8(buf).expect("the writers only ever write whole strings"))
+    }
+
+    /// Like [`Templates::render`], but writes the page straight to `path` as it is
+    /// rendered instead of holding all of it in memory
+    ///
+    /// It is written to a file next to `path` first and only moved there once all of it
+    /// is, so a page that fails part of the way through is not left behind cut short.
+    pub fn render_to_file(
+        &self,
+        name: &str,
+        output: &str,
+        path: &Path,
+        context: Value,
+        fallback: impl FnOnce(&mut dyn HtmlWriter) -> html::Result,
+    ) -> Result<(), Error> {
+        let html = self.render_template(name, output, context)?;
+        cache::create_parent(path)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{name}.tmp"));
+        let file = File::create(&temp).map_err(Error::io("write", &temp))?;
+        let result = self
+            .write(output, path, html, BufWriter::new(file), fallback)
+            .and_then(|()| std::fs::rename(&temp, path).map_err(Error::io("write", path)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Writes the rendered template `html` for `output` to `path` through `writer`, in
+    /// the style pages are written in, or `fallback` if there was no template
+    fn write(
+        &self,
+        output: &str,
+        path: &Path,
+        html: Option<String>,
+        writer: impl io::Write,
+        fallback: impl FnOnce(&mut dyn HtmlWriter) -> html::Result,
+    ) -> Result<(), Error> {
+        let mut writer = IoWriter::new(writer);
+        let result = match (html, self.style) {
+            // Passing it through a writer would add a line break at the end
+            (Some(html), HtmlStyle::AsIs) => writer.write_str(&html).map_err(html::Error::from),
+            (Some(html), style) => style.write(&mut writer, |x| x.write_string_lit(&html)),
+            (None, style) => style.write(&mut writer, fallback),
+        };
+        writer.finish().map_err(Error::io("write", path))?;
+        result.map_err(|source| Error::Render {
+            output: output.to_string(),
+            source,
+        })
+    }
+
+    /// The template `name` rendered with `context`, `None` if the site does not have one
+    fn render_template(
+        &self,
+        name: &str,
+        output: &str,
+        context: Value,
+    ) -> Result<Option<String>, Error> {
+        let Some(env) = &self.env else {
+            return Ok(None);
+        };
+        let error = |source| Error::Template {
+            template: self.dir.join(name),
+            output: output.to_string(),
+            source,
+        };
+        let template = match env.get_template(name) {
+            Ok(template) => template,
+            Err(err) if err.kind() == ErrorKind::TemplateNotFound => return Ok(None),
+            Err(err) => return Err(error(err)),
+        };
+        template.render(context).map(Some).map_err(error)
+    }
+}
+
+/// Escapes like minijinja does, but leaves the slashes in URLs alone
+///
+/// Quotes of both kinds are escaped, as templates may put values in attributes quoted
+/// either way.
+fn formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), minijinja::Error> {
+    match value.as_str() {
+        Some(text) if state.auto_escape() == AutoEscape::Html && !value.is_safe() => {
+            out.write_str(&escape_attribute(text).replace('\'', "&#x27;"))?;
+            Ok(())
+        }
+        _ => escape_formatter(out, state, value),
+    }
+}
+
+/// A tag or category as seen by templates
+#[derive(Serialize)]
+pub struct TermContext {
+    pub name: String,
+    pub url: String,
+}
+
+/// A post as seen by templates, `content` is only filled in for the page being rendered
+#[derive(Serialize)]
+pub struct PageContext<'a> {
+    pub title: &'a str,
+    pub url: &'a str,
+    /// RFC 3339, for `datetime` attributes
+    pub timestamp: String,
+    /// RFC 2822, like the built in layout shows
+    pub date: String,
+    /// RFC 3339, from `updated` in the frontmatter or else the timestamp
+    pub lastmod: String,
+    /// Whether search engines should be kept away from the page
+    pub noindex: bool,
+    pub tags: Vec<TermContext>,
+    pub categories: Vec<TermContext>,
+    pub extra: &'a BTreeMap<String, frontmatter::Value>,
+    pub content: Option<Value>,
+    /// Only filled in alongside `content`, and left empty unless the page asked for one
+    pub toc: Vec<TocEntry>,
+    /// Pages that link to this one with a cross reference, newest first
+    pub backlinks: &'a [Target],
+}
+
+impl<'a> PageContext<'a> {
+    pub fn new(page: &'a Page, taxonomies: &[Taxonomy; 2]) -> Self {
+        let terms = |taxonomy: &Taxonomy, terms: &[String]| {
+            terms
+                .iter()
+                .map(|term| TermContext {
+                    name: term.clone(),
+                    url: taxonomy.term_url(&slugify(term)),
+                })
+                .collect()
+        };
+        PageContext {
+            title: &page.title,
+            url: &page.url,
+            timestamp: page.timestamp.to_rfc3339(),
+            date: page.timestamp.to_rfc2822(),
+            lastmod: page.lastmod().to_rfc3339(),
+            noindex: page.draft || page.noindex,
+            tags: terms(&taxonomies[0], &page.tags),
+            categories: terms(&taxonomies[1], &page.categories),
+            extra: &page.extra,
+            content: None,
+            toc: Vec::new(),
+            backlinks: &page.backlinks,
+        }
+    }
+
+    /// Includes the rendered body, which is trusted and not escaped again
+    pub fn with_content(mut self, page: &Page) -> Self {
+        self.content = Some(Value::from_safe_string(page.body().to_string()));
+        if page.show_toc {
+            self.toc = page.toc();
+        }
+        self
+    }
+}
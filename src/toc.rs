@@ -0,0 +1,240 @@
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use comrak::{
+    adapters::{HeadingAdapter, HeadingMeta},
+    html::Anchorizer,
+    nodes::Sourcepos,
+};
+use serde::{Deserialize, Serialize};
+use trowel::{
+    html::{self, escape_attribute},
+    trowel_html, HtmlWriter, ToHtml,
+};
+
+/// A heading as it appears in the page, in document order
+#[derive(Clone, Debug)]
+pub struct Heading {
+    pub level: u8,
+    /// Anchor id, unique within the page
+    pub id: String,
+    pub title: String,
+}
+
+/// A heading in the table of contents, with the headings under it
+#[derive(Clone, Debug, Serialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Which headings make it into a table of contents
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TocOptions {
+    pub min_level: u8,
+    pub max_level: u8,
+}
+
+impl Default for TocOptions {
+    fn default() -> Self {
+        TocOptions {
+            min_level: 2,
+            max_level: 3,
+        }
+    }
+}
+
+/// Nests the `headings` between the levels in `options` under each other,
+/// a heading that skips a level goes under the closest one above it
+pub fn nest(headings: &[Heading], options: &TocOptions) -> Vec<TocEntry> {
+    let mut roots = Vec::new();
+    // The entries that can still get children, each deeper than the last
+    let mut open: Vec<TocEntry> = Vec::new();
+    let close = |open: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>| {
+        let entry = open.pop().unwrap();
+        match open.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => roots.push(entry),
+        }
+    };
+    for heading in headings
+        .iter()
+        .filter(|x| (options.min_level..=options.max_level).contains(&x.level))
+    {
+        while open.last().is_some_and(|x| x.level >= heading.level) {
+            close(&mut open, &mut roots);
+        }
+        open.push(TocEntry {
+            level: heading.level,
+            id: heading.id.clone(),
+            title: heading.title.clone(),
+            children: Vec::new(),
+        });
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
+
+/// Renders `entries` as nested lists of links to the headings
+pub fn nav(entries: &[TocEntry]) -> impl Fn(&mut dyn HtmlWriter) -> html::Result + '_ {
+    move |writer: &mut dyn HtmlWriter| {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        (trowel_html! {
+            nav(class: "toc") {
+                {list(entries)}
+            }
+        })
+        .to_html(writer)
+    }
+}
+
+fn list(entries: &[TocEntry]) -> impl Fn(&mut dyn HtmlWriter) -> html::Result + '_ {
+    move |writer: &mut dyn HtmlWriter| {
+        (trowel_html! {
+            ol {
+                {
+                    entries.iter().map(|entry| {
+                        trowel_html! { move
+                            li {
+                                a(href: {Some(format!("#{}", entry.id))}) {
+                                    {entry.title.as_str()}
+                                },
+                                {(!entry.children.is_empty()).then_some(list(&entry.children))}
+                            }
+                        }
+                    }).collect::<Vec<_>>()
+                }
+            }
+        })
+        .to_html(writer)
+    }
+}
+
+/// Gives every heading comrak renders an id and keeps track of them
+pub struct HeadingCollector {
+    /// Put in front of every id
+    prefix: String,
+    state: Mutex<(Anchorizer, Vec<Heading>)>,
+}
+
+impl HeadingCollector {
+    pub fn new(prefix: String) -> Self {
+        HeadingCollector {
+            prefix,
+            state: Mutex::new((Anchorizer::new(), Vec::new())),
+        }
+    }
+
+    pub fn into_headings(self) -> Vec<Heading> {
+        self.state.into_inner().unwrap().1
+    }
+}
+
+impl HeadingAdapter for HeadingCollector {
+    fn enter(
+        &self,
+        output: &mut dyn Write,
+        heading: &HeadingMeta,
+        _sourcepos: Option<Sourcepos>,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let (anchorizer, headings) = &mut *state;
+        let id = format!(
+            "{}{}",
+            self.prefix,
+            anchorizer.anchorize(heading.content.clone())
+        );
+        write!(
+            output,
+            "<h{} id=\"{}\">",
+            heading.level,
+            escape_attribute(&id)
+        )?;
+        headings.push(Heading {
+            level: heading.level,
+            id,
+            title: heading.content.clone(),
+        });
+        Ok(())
+    }
+
+    fn exit(&self, output: &mut dyn Write, heading: &HeadingMeta) -> io::Result<()> {
+        writeln!(output, "</h{}>", heading.level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trowel::util::html_to_string;
+
+    use super::*;
+
+    fn headings(levels: &[u8]) -> Vec<Heading> {
+        levels
+            .iter()
+            .enumerate()
+            .map(|(i, level)| Heading {
+                level: *level,
+                id: format!("h{i}"),
+                title: format!("Heading {i}"),
+            })
+            .collect()
+    }
+
+    /// `entries` as `id(children)`, e.g. `h0(h1 h2) h3`
+    fn outline(entries: &[TocEntry]) -> String {
+        entries
+            .iter()
+            .map(|x| match x.children.is_empty() {
+                true => x.id.clone(),
+                false => format!("{}({})", x.id, outline(&x.children)),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn nested(levels: &[u8], min_level: u8, max_level: u8) -> String {
+        let options = TocOptions {
+            min_level,
+            max_level,
+        };
+        outline(&nest(&headings(levels), &options))
+    }
+
+    #[test]
+    fn nests_headings_under_the_ones_above() {
+        assert_eq!(nested(&[2, 3, 3, 2, 3], 2, 3), "h0(h1 h2) h3(h4)");
+        assert_eq!(nested(&[2, 3, 4, 3, 2], 2, 4), "h0(h1(h2) h3) h4");
+    }
+
+    #[test]
+    fn puts_skipped_levels_under_the_closest_heading() {
+        assert_eq!(nested(&[2, 4, 3], 2, 4), "h0(h1 h2)");
+        assert_eq!(nested(&[3, 2, 4], 2, 4), "h0 h1(h2)");
+    }
+
+    #[test]
+    fn leaves_out_levels_outside_the_options() {
+        assert_eq!(nested(&[1, 2, 3, 4, 2], 2, 3), "h1(h2) h4");
+        assert_eq!(nested(&[1, 4], 2, 3), "");
+    }
+
+    #[test]
+    fn renders_nested_lists() {
+        let entries = nest(&headings(&[2, 3]), &TocOptions::default());
+        assert_eq!(
+            html_to_string(nav(&entries)).unwrap(),
+            "<nav class=\"toc\"><ol><li><a href=\"#h0\">Heading 0\n</a><ol><li><a href=\"#h1\">Heading 1\n</a></li></ol></li></ol></nav>"
+        );
+        assert_eq!(html_to_string(nav(&[])).unwrap(), "");
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{error::Error, markdown, Page};
+
+/// What markdown links to other pages start with, `[text](@/other-post.md)`
+pub const CROSS_REFERENCE: &str = "@/";
+
+/// The page a cross reference resolves to
+#[derive(Clone, Debug, Serialize)]
+pub struct Target {
+    pub url: String,
+    pub title: String,
+}
+
+/// Resolves the cross references in every page's markdown against the other `pages`,
+/// which are looked up by file name, and fills in which pages link to each
+pub fn link(pages: &mut [Page]) -> Vec<Error> {
+    let index = pages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, page)| Some((page.path.file_stem()?.to_str()?.to_string(), i)))
+        .collect::<HashMap<_, _>>();
+    let found = pages
+        .par_iter()
+        .map(|page| markdown::references(&page.markdown, &page.markdown_options()))
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    // Indices of the pages linking to each page
+    let mut linked_from = vec![BTreeSet::new(); pages.len()];
+    for (i, references) in found.into_iter().enumerate() {
+        let mut resolved = BTreeMap::new();
+        for (written, line) in references {
+            let (name, fragment) = match written.split_once('#') {
+                Some((name, fragment)) => (name, Some(fragment)),
+                None => (written.as_str(), None),
+            };
+            let name = name.strip_prefix(CROSS_REFERENCE).unwrap_or(name);
+            let name = name.strip_suffix(".md").unwrap_or(name);
+            // A bare fragment is a link within the page itself
+            let target = if name.is_empty() && fragment.is_some() {
+                i
+            } else if let Some(target) = index.get(name) {
+                *target
+            } else {
+                errors.push(Error::UnknownReference {
+                    file: pages[i].path.clone(),
+                    line: pages[i].markdown_line + line,
+                    name: name.to_string(),
+                });
+                continue;
+            };
+            let mut url = pages[target].url.clone();
+            if let Some(fragment) = fragment {
+                url = format!("{url}#{fragment}");
+            }
+            if target != i {
+                linked_from[target].insert(i);
+            }
+            resolved.insert(
+                written,
+                Target {
+                    url,
+                    title: pages[target].title.clone(),
+                },
+            );
+        }
+        pages[i].references = resolved;
+    }
+    for (i, sources) in linked_from.into_iter().enumerate() {
+        let mut sources = sources.into_iter().collect::<Vec<_>>();
+        sources.sort_by_key(|x| Reverse(pages[*x].timestamp));
+        pages[i].backlinks = sources
+            .into_iter()
+            .map(|x| Target {
+                url: pages[x].url.clone(),
+                title: pages[x].title.clone(),
+            })
+            .collect();
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn page(name: &str, timestamp: &str, markdown: &str) -> Page {
+        let source = format!("---\ntitle: Title {name}\ntimestamp: {timestamp}\n---\n{markdown}");
+        Page::parse(Path::new(&format!("pages/{name}.md")), &source).unwrap()
+    }
+
+    fn urls(page: &Page) -> Vec<(&str, &str)> {
+        page.references
+            .iter()
+            .map(|(written, target)| (written.as_str(), target.url.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn resolves_references_by_file_name() {
+        let mut pages = [
+            page(
+                "a",
+                "2024-01-01T00:00:00Z",
+                "[b](@/b.md) and [b's end](@/b#end)\n",
+            ),
+            page("b", "2024-02-01T00:00:00Z", "[here](#top) and [[a]]\n"),
+        ];
+        assert!(link(&mut pages).is_empty());
+        assert_eq!(
+            urls(&pages[0]),
+            [("@/b#end", "/b.html#end"), ("@/b.md", "/b.html")]
+        );
+        assert_eq!(urls(&pages[1]), [("a", "/a.html")]);
+        assert_eq!(pages[0].references["@/b.md"].title, "Title b");
+    }
+
+    #[test]
+    fn collects_backlinks_newest_first() {
+        let mut pages = [
+            page(
+                "a",
+                "2024-01-01T00:00:00Z",
+                "[c](@/c.md) [c again](@/c.md#x)\n",
+            ),
+            page("b", "2024-02-01T00:00:00Z", "[c](@/c.md)\n"),
+            page("c", "2024-03-01T00:00:00Z", "[self](@/c.md)\n"),
+        ];
+        assert!(link(&mut pages).is_empty());
+        let backlinks = pages[2]
+            .backlinks
+            .iter()
+            .map(|x| x.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(backlinks, ["/b.html", "/a.html"]);
+        assert!(pages[0].backlinks.is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_pages_with_their_line() {
+        let mut pages = [page(
+            "a",
+            "2024-01-01T00:00:00Z",
+            "text\n\n[x](@/missing.md)\n",
+        )];
+        let errors = link(&mut pages);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "pages/a.md:7: no page named \"missing\""
+        );
+    }
+}
@@ -1,66 +1,292 @@
+use std::borrow::Cow;
 use std::fmt::Write;
+use std::{fmt, io};
 
 pub type Attribute = (String, Option<String>);
 
+pub type Result<T = ()> = std::result::Result<T, Error>;
+
+/// Why writing HTML failed, and in which element
+#[derive(Debug)]
+pub struct Error {
+    /// The elements and components it failed in, innermost first
+    path: Vec<String>,
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl Error {
+    /// An error from a component or an expression, e.g. `Error::new("no title")`
+    pub fn new(source: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Error {
+            path: Vec::new(),
+            source: source.into(),
+        }
+    }
+
+    /// Records that the error happened inside `element`, `trowel_html!` does this for
+    /// every element and component it goes through
+    pub fn within(mut self, element: &str) -> Self {
+        self.path.push(element.to_string());
+        self
+    }
+
+    /// The elements and components it failed in, outermost first
+    pub fn path(&self) -> impl Iterator<Item = &str> {
+        self.path.iter().rev().map(|x| x.as_str())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "in {}: ", self.path().collect::<Vec<_>>().join(" > "))?;
+        }
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(err: fmt::Error) -> Self {
+        Error::new(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::new(err)
+    }
+}
+
 pub trait HtmlWriter {
-    fn write_tag(&mut self, tag: &str, single: bool, attributes: Vec<Attribute>);
-    fn write_end_tag(&mut self, tag: &str);
-    fn write_string_lit(&mut self, lit: &str);
+    fn write_tag(&mut self, tag: &str, single: bool, attributes: Vec<Attribute>) -> Result;
+    fn write_end_tag(&mut self, tag: &str) -> Result;
+    /// Writes `lit` verbatim, it must already be valid markup
+    fn write_string_lit(&mut self, lit: &str) -> Result;
+    /// Writes `text` as a text node, escaping it for the element it is in
+    fn write_text(&mut self, text: &str) -> Result;
+}
+
+/// Writes the element `tag` with `children` in it, recording it in the path of any
+/// error on the way, for `trowel_html!`
+#[doc(hidden)]
+pub fn write_element(
+    writer: &mut dyn HtmlWriter,
+    tag: &str,
+    single: bool,
+    attributes: Vec<Attribute>,
+    children: impl FnOnce(&mut dyn HtmlWriter) -> Result,
+) -> Result {
+    let write = || -> Result {
+        writer.write_tag(tag, single, attributes)?;
+        children(writer)?;
+        if !single {
+            writer.write_end_tag(tag)?;
+        }
+        Ok(())
+    };
+    write().map_err(|err| err.within(tag))
 }
 
 pub trait ToHtml {
-    fn to_html(self, writer: &mut dyn HtmlWriter);
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result;
 }
 
-impl<F: FnOnce(&mut dyn crate::HtmlWriter)> ToHtml for F {
-    fn to_html(self, writer: &mut dyn HtmlWriter) {
+impl<F: FnOnce(&mut dyn crate::HtmlWriter) -> Result> ToHtml for F {
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
         self(writer)
     }
 }
 
 impl ToHtml for &str {
-    fn to_html(self, writer: &mut dyn HtmlWriter) {
-        writer.write_string_lit(self);
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
+        writer.write_text(self)
     }
 }
 
 impl ToHtml for String {
-    fn to_html(self, writer: &mut dyn HtmlWriter) {
-        writer.write_string_lit(self.as_str());
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
+        writer.write_text(self.as_str())
     }
 }
 
 impl<T> ToHtml for Vec<T>
 where
-    T: Fn(&mut dyn HtmlWriter),
+    T: Fn(&mut dyn HtmlWriter) -> Result,
 {
-    fn to_html(self, writer: &mut dyn HtmlWriter) {
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
         for el in self {
-            el(writer)
+            el(writer)?;
         }
+        Ok(())
     }
 }
 
 impl<T: ToHtml> ToHtml for Option<T> {
-    fn to_html(self, writer: &mut dyn HtmlWriter) {
-        if let Some(x) = self {
-            x.to_html(writer)
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
+        match self {
+            Some(x) => x.to_html(writer),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the value or fails with the error, so components and expressions that can
+/// fail can be used as is
+impl<T: ToHtml, E: Into<Error>> ToHtml for std::result::Result<T, E> {
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
+        self.map_err(Into::into)?.to_html(writer)
+    }
+}
+
+/// Trusted markup that is written without any escaping
+pub struct Raw<T: AsRef<str>>(pub T);
+
+impl<T: AsRef<str>> ToHtml for Raw<T> {
+    fn to_html(self, writer: &mut dyn HtmlWriter) -> Result {
+        writer.write_string_lit(self.0.as_ref())
+    }
+}
+
+/// Elements whose content is not parsed for character references
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+pub fn is_raw_text_element(tag: &str) -> bool {
+    RAW_TEXT_ELEMENTS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(tag))
+}
+
+/// Fails if `text` has the end tag of `tag`, the raw text element it is written into,
+/// in it, which would close it early and which no escaping can prevent there
+pub fn check_raw_text(text: &str, tag: &str) -> Result {
+    let lower = text.to_ascii_lowercase();
+    let tag = tag.to_ascii_lowercase();
+    if lower.contains(&format!("</{tag}")) {
+        Err(Error::new(format!(
+            "text would end the {tag} element it is in early"
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn escape(input: &str, special: impl Fn(char) -> Option<&'static str>) -> Cow<'_, str> {
+    let Some(first) = input.find(|c| special(c).is_some()) else {
+        return Cow::Borrowed(input);
+    };
+    let mut out = String::with_capacity(input.len() + 8);
+    out.push_str(&input[..first]);
+    for c in input[first..].chars() {
+        match special(c) {
+            Some(entity) => out.push_str(entity),
+            None => out.push(c),
         }
     }
+    Cow::Owned(out)
+}
+
+/// Escapes text for use as element content
+pub fn escape_text(text: &str) -> Cow<'_, str> {
+    escape(text, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    })
+}
+
+/// Escapes text for use inside a double-quoted attribute value
+pub fn escape_attribute(value: &str) -> Cow<'_, str> {
+    escape(value, |c| match c {
+        '&' => Some("&amp;"),
+        '"' => Some("&quot;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    })
+}
+
+/// Lets the writers here, which write to a [`fmt::Write`], stream to an [`io::Write`]
+/// such as a file instead
+///
+/// A [`fmt::Error`] can not say what went wrong, so the first error is held on to and
+/// returned by [`IoWriter::finish`], and nothing more is written after it.
+pub struct IoWriter<W: io::Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> IoWriter<W> {
+    pub fn new(writer: W) -> Self {
+        IoWriter {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flushes everything written, returning the first error there was
+    ///
+    /// Check this before whatever the writing returned, the error here is what stopped
+    /// it, which a writer on top only saw as a [`fmt::Error`].
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.error.is_some() {
+            return Err(fmt::Error);
+        }
+        self.writer.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
 }
 
 pub struct WriteHtml<T: Write> {
     writer: T,
+    raw_text: Option<String>,
+    xml: bool,
 }
 
 impl<T: Write> WriteHtml<T> {
     pub fn new(writer: T) -> Self {
-        WriteHtml { writer }
+        WriteHtml {
+            writer,
+            raw_text: None,
+            xml: false,
+        }
+    }
+
+    /// Writes XML instead of HTML, every element's content is escaped and
+    /// text is written exactly as given
+    pub fn xml(writer: T) -> Self {
+        WriteHtml {
+            writer,
+            raw_text: None,
+            xml: true,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.writer
     }
 }
 
 impl<T: Write> HtmlWriter for WriteHtml<T> {
-    fn write_tag(&mut self, tag: &str, single: bool, attr: Vec<Attribute>) {
+    fn write_tag(&mut self, tag: &str, single: bool, attr: Vec<Attribute>) -> Result {
         let end = if single { " /" } else { "" };
         let mut attr_str = String::new();
         for (key, value) in attr {
@@ -69,18 +295,154 @@ impl<T: Write> HtmlWriter for WriteHtml<T> {
             if let Some(value) = value {
                 attr_str.push('=');
                 attr_str.push('"');
-                attr_str.push_str(&value);
+                attr_str.push_str(&escape_attribute(&value));
                 attr_str.push('"');
             }
         }
-        write!(self.writer, "<{tag}{attr_str}{end}>").unwrap();
+        write!(self.writer, "<{tag}{attr_str}{end}>")?;
+        if !single && !self.xml && self.raw_text.is_none() && is_raw_text_element(tag) {
+            self.raw_text = Some(tag.to_string());
+        }
+        Ok(())
+    }
+
+    fn write_end_tag(&mut self, tag: &str) -> Result {
+        if self
+            .raw_text
+            .as_ref()
+            .is_some_and(|x| x.eq_ignore_ascii_case(tag))
+        {
+            self.raw_text = None;
+        }
+        write!(self.writer, "</{tag}>")?;
+        Ok(())
+    }
+
+    fn write_string_lit(&mut self, lit: &str) -> Result {
+        if self.xml {
+            write!(self.writer, "{lit}")?;
+        } else {
+            writeln!(self.writer, "{lit}")?;
+        }
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> Result {
+        if let Some(tag) = &self.raw_text {
+            check_raw_text(text, tag)?;
+            self.write_string_lit(text)
+        } else {
+            self.write_string_lit(&escape_text(text))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attribute, Error, HtmlWriter, Result, WriteHtml};
+    use crate::{
+        trowel_html,
+        util::{html_to_minified_string, html_to_pretty_string, html_to_string},
+        Raw,
+    };
+
+    #[test]
+    fn escapes_text() {
+        let html = html_to_string(trowel_html! { p { {"a < b && c > d"} } }).unwrap();
+        assert_eq!(html, "<p>a &lt; b &amp;&amp; c &gt; d\n</p>");
+    }
+
+    #[test]
+    fn escapes_attributes() {
+        let html = html_to_string(trowel_html! {
+            a(href: {Some("/x\"><script>alert(1)</script>")}) {}
+        })
+        .unwrap();
+        assert_eq!(
+            html,
+            "<a href=\"/x&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\"></a>"
+        );
+    }
+
+    #[test]
+    fn writes_raw_text_as_is() {
+        let html = html_to_string(trowel_html! {
+            script { {"if (a < b && c) {}"} },
+            style { {"a > b { color: red }"} }
+        })
+        .unwrap();
+        assert_eq!(
+            html,
+            "<script>if (a < b && c) {}\n</script><style>a > b { color: red }\n</style>"
+        );
     }
 
-    fn write_end_tag(&mut self, tag: &str) {
-        write!(self.writer, "</{tag}>").unwrap();
+    #[test]
+    fn rejects_end_tags_in_raw_text() {
+        let injection = "</script><img src=x onerror=alert(1)>";
+        assert!(html_to_string(trowel_html! { script { {injection} } }).is_err());
+        assert!(html_to_string(trowel_html! { style { {"</STYLE >"} } }).is_err());
+        assert!(html_to_minified_string(trowel_html! { script { {injection} } }).is_err());
+        assert!(html_to_pretty_string(trowel_html! { script { {injection} } }).is_err());
     }
 
-    fn write_string_lit(&mut self, lit: &str) {
-        writeln!(self.writer, "{lit}").unwrap()
+    #[test]
+    fn allows_other_end_tags_in_raw_text() {
+        let css = "a { content: '</script>' }";
+        let js = "document.write('</style>')";
+        assert!(html_to_string(trowel_html! { style { {css} } }).is_ok());
+        assert!(html_to_string(trowel_html! { script { {js} } }).is_ok());
+        assert!(html_to_minified_string(trowel_html! { style { {css} } }).is_ok());
+        assert!(html_to_pretty_string(trowel_html! { style { {css} } }).is_ok());
+    }
+
+    #[test]
+    fn writes_raw_markup_as_is() {
+        let html = html_to_string(trowel_html! { div { {Raw("<b>a & b</b>")} } }).unwrap();
+        assert_eq!(html, "<div><b>a & b</b>\n</div>");
+    }
+
+    #[allow(non_snake_case)]
+    fn Comp(
+        _attrs: Vec<Attribute>,
+        _children: impl Fn(&mut dyn HtmlWriter) -> Result,
+    ) -> Result<&'static str> {
+        Err(Error::new("no title"))
+    }
+
+    fn title() -> Result<&'static str> {
+        Err(Error::new("no title"))
+    }
+
+    #[test]
+    fn puts_the_element_path_in_errors() {
+        let err = html_to_string(trowel_html! {
+            html {
+                head {},
+                body {
+                    !Comp {
+                        p { "a" }
+                    }
+                }
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.path().collect::<Vec<_>>(), ["html", "body", "Comp"]);
+        assert_eq!(err.to_string(), "in html > body > Comp: no title");
+    }
+
+    #[test]
+    fn stops_at_a_failed_expression() {
+        let mut buf = String::new();
+        let mut writer = WriteHtml::new(&mut buf);
+        let err = (trowel_html! {
+            div {
+                h1 { {title()?} },
+                p { "after" }
+            }
+        })(&mut writer)
+        .unwrap_err();
+        assert_eq!(err.to_string(), "in div > h1: no title");
+        assert!(!buf.contains("after"), "went on to write {buf}");
     }
 }
@@ -1,5 +1,9 @@
 extern crate self as trowel;
 pub use trowel_macro::trowel_html;
 pub mod html;
+pub mod minify;
+pub mod pretty;
+mod tokenize;
 pub mod util;
-pub use html::{HtmlWriter, ToHtml};
+pub use html::{HtmlWriter, Raw, ToHtml};
+pub use tokenize::{parse_tag, Tag};
@@ -0,0 +1,493 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    html::{
+        check_raw_text, escape_attribute, escape_text, is_raw_text_element, Attribute, HtmlWriter,
+        Result,
+    },
+    tokenize::{contains, is_block, tokenize, Tokens, PREFORMATTED_ELEMENTS, VOID_ELEMENTS},
+};
+
+/// Elements a `p` can not contain, so one starting ends it
+///
+/// Not `table`, which leaves the `p` open in documents without a doctype.
+const CLOSES_P: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "ul",
+];
+
+/// Whether the end tag of `tag` can be left out when `next` starts right after it
+fn end_implied_by_start(tag: &str, next: &str) -> bool {
+    match tag {
+        "li" => next == "li",
+        "dt" | "dd" => matches!(next, "dt" | "dd"),
+        "p" => contains(CLOSES_P, next),
+        "tr" => next == "tr",
+        "td" | "th" => matches!(next, "td" | "th"),
+        "option" => matches!(next, "option" | "optgroup"),
+        _ => false,
+    }
+}
+
+/// Whether the end tag of `tag` can be left out when `parent` ends right after it
+fn end_implied_by_parent_end(tag: &str, parent: &str) -> bool {
+    match tag {
+        "li" | "dd" | "tr" | "td" | "th" | "option" => true,
+        "p" => !matches!(
+            parent,
+            "a" | "audio" | "del" | "ins" | "map" | "noscript" | "video"
+        ),
+        _ => false,
+    }
+}
+
+/// Writes HTML with as little in it as it takes to get the same page
+///
+/// Whitespace is collapsed outside `pre`, `textarea`, `script` and `style` and left out
+/// between block elements, comments are dropped, attribute values are only quoted when
+/// they have to be and end tags the parser implies anyway are left out. Markup written
+/// with [`HtmlWriter::write_string_lit`] is minified the same way.
+pub struct MinifyHtml<T: Write> {
+    writer: T,
+    /// Open elements, innermost last
+    open: Vec<String>,
+    /// Whitespace held back until it is known whether it shows
+    space: bool,
+    /// Whether the last tag written was a block element's, with nothing after it yet
+    after_block: bool,
+    /// An end tag held back until it is known whether the next tag implies it
+    pending_end: Option<String>,
+}
+
+impl<T: Write> MinifyHtml<T> {
+    pub fn new(writer: T) -> Self {
+        MinifyHtml {
+            writer,
+            open: Vec::new(),
+            space: false,
+            // Whitespace at the very start does not show either
+            after_block: true,
+            pending_end: None,
+        }
+    }
+
+    fn in_raw_text(&self) -> bool {
+        self.open.last().is_some_and(|x| is_raw_text_element(x))
+    }
+
+    fn in_preformatted(&self) -> bool {
+        self.open.iter().any(|x| contains(PREFORMATTED_ELEMENTS, x))
+    }
+
+    /// Inside SVG or MathML, where self closing tags still mean something
+    fn in_foreign(&self) -> bool {
+        self.open.iter().any(|x| x == "svg" || x == "math")
+    }
+
+    fn flush_pending_end(&mut self) -> fmt::Result {
+        if let Some(tag) = self.pending_end.take() {
+            write!(self.writer, "</{tag}>")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the held back whitespace, unless it is next to a block element's tag,
+    /// `block` being whether what comes next is one
+    fn flush_space(&mut self, block: bool) -> fmt::Result {
+        if std::mem::take(&mut self.space) && !block && !self.after_block {
+            self.writer.write_char(' ')?;
+        }
+        Ok(())
+    }
+
+    /// Writes anything still held back, call once everything has been written
+    pub fn finish(mut self) -> Result<T> {
+        self.flush_pending_end()?;
+        Ok(self.writer)
+    }
+}
+
+/// Whether the attribute value can go without quotes, it is escaped already
+fn can_be_unquoted(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|x| x.is_ascii_whitespace() || matches!(x, '"' | '\'' | '=' | '<' | '>' | '`'))
+}
+
+impl<T: Write> Tokens for MinifyHtml<T> {
+    fn raw_text(&self) -> Option<String> {
+        self.open.last().filter(|x| is_raw_text_element(x)).cloned()
+    }
+
+    fn start_tag(
+        &mut self,
+        name: &str,
+        attributes: &[(&str, Option<&str>)],
+        self_closing: bool,
+    ) -> fmt::Result {
+        let tag = name.to_ascii_lowercase();
+        if let Some(pending) = &self.pending_end {
+            if end_implied_by_start(pending, &tag) {
+                self.pending_end = None;
+            }
+        }
+        self.flush_pending_end()?;
+        let block = is_block(&tag, &self.open);
+        self.flush_space(block)?;
+
+        let foreign = self.in_foreign() || tag == "svg" || tag == "math";
+        write!(self.writer, "<{name}")?;
+        for (i, (name, value)) in attributes.iter().enumerate() {
+            // The slash of a `/>` right after an unquoted value would be read as part of it
+            let before_slash = self_closing && foreign && i + 1 == attributes.len();
+            write!(self.writer, " {name}")?;
+            match value {
+                None => {}
+                Some(value) if can_be_unquoted(value) && !before_slash => {
+                    write!(self.writer, "={value}")?
+                }
+                Some(value) if !value.contains('"') => write!(self.writer, "=\"{value}\"")?,
+                Some(value) => write!(self.writer, "='{value}'")?,
+            }
+        }
+        if self_closing && foreign {
+            self.writer.write_str("/>")?;
+        } else {
+            self.writer.write_char('>')?;
+            if !contains(VOID_ELEMENTS, &tag) {
+                self.open.push(tag);
+            }
+        }
+        self.after_block = block;
+        Ok(())
+    }
+
+    fn end_tag(&mut self, name: &str) -> fmt::Result {
+        let tag = name.to_ascii_lowercase();
+        if let Some(pending) = &self.pending_end {
+            if end_implied_by_parent_end(pending, &tag) {
+                self.pending_end = None;
+            }
+        }
+        self.flush_pending_end()?;
+        let block = is_block(&tag, &self.open);
+        self.flush_space(block)?;
+        if let Some(i) = self.open.iter().rposition(|x| *x == tag) {
+            self.open.truncate(i);
+        }
+        let optional = matches!(
+            tag.as_str(),
+            "li" | "dt" | "dd" | "p" | "tr" | "td" | "th" | "option"
+        );
+        if optional && !self.in_foreign() {
+            self.pending_end = Some(tag);
+        } else {
+            write!(self.writer, "</{name}>")?;
+        }
+        self.after_block = block;
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> fmt::Result {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.in_raw_text() || self.in_preformatted() {
+            self.flush_pending_end()?;
+            self.flush_space(false)?;
+            self.writer.write_str(text)?;
+            self.after_block = false;
+            return Ok(());
+        }
+        if text.starts_with(|x: char| x.is_ascii_whitespace()) {
+            self.space = true;
+        }
+        for (i, word) in text.split_ascii_whitespace().enumerate() {
+            self.space |= i > 0;
+            self.flush_pending_end()?;
+            self.flush_space(false)?;
+            self.writer.write_str(word)?;
+            self.after_block = false;
+        }
+        if text.ends_with(|x: char| x.is_ascii_whitespace()) {
+            self.space = true;
+        }
+        Ok(())
+    }
+
+    fn verbatim(&mut self, markup: &str) -> fmt::Result {
+        self.flush_pending_end()?;
+        self.flush_space(true)?;
+        self.writer.write_str(markup)?;
+        self.after_block = true;
+        Ok(())
+    }
+
+    fn comment(&mut self, comment: &str) -> fmt::Result {
+        // Conditional comments are for old browsers to read
+        if comment.starts_with("<!--[if") {
+            self.verbatim(comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Write> HtmlWriter for MinifyHtml<T> {
+    fn write_tag(&mut self, tag: &str, single: bool, attributes: Vec<Attribute>) -> Result {
+        let escaped = attributes
+            .iter()
+            .map(|(name, value)| (name, value.as_deref().map(escape_attribute)))
+            .collect::<Vec<_>>();
+        let attributes = escaped
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+            .collect::<Vec<_>>();
+        self.start_tag(tag, &attributes, single)?;
+        Ok(())
+    }
+
+    fn write_end_tag(&mut self, tag: &str) -> Result {
+        self.end_tag(tag)?;
+        Ok(())
+    }
+
+    fn write_string_lit(&mut self, lit: &str) -> Result {
+        tokenize(lit, self)?;
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> Result {
+        if let Some(tag) = self.raw_text() {
+            check_raw_text(text, &tag)?;
+            self.text(text)?;
+        } else {
+            self.text(&escape_text(text))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use scraper::{ElementRef, Html, Node};
+
+    use super::*;
+
+    fn minify(html: &str) -> String {
+        let mut writer = MinifyHtml::new(String::new());
+        writer.write_string_lit(html).unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// The document a browser makes of `html`, without comments and with whitespace
+    /// collapsed and left out next to block elements, where it does not show
+    pub(crate) fn dom(html: &str) -> String {
+        let mut out = String::new();
+        write_element(Html::parse_document(html).root_element(), false, &mut out);
+        out
+    }
+
+    /// Elements browsers lay out as blocks or do not show at all, kept apart from the
+    /// list the writers use so a mistake in that one shows up here
+    const DISPLAY_BLOCK: &[&str] = &[
+        "address",
+        "article",
+        "aside",
+        "base",
+        "blockquote",
+        "body",
+        "caption",
+        "col",
+        "colgroup",
+        "dd",
+        "details",
+        "div",
+        "dl",
+        "dt",
+        "fieldset",
+        "figcaption",
+        "figure",
+        "footer",
+        "form",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "head",
+        "header",
+        "hr",
+        "html",
+        "legend",
+        "li",
+        "main",
+        "nav",
+        "ol",
+        "p",
+        "pre",
+        "section",
+        "summary",
+        "table",
+        "tbody",
+        "td",
+        "tfoot",
+        "th",
+        "thead",
+        "title",
+        "tr",
+        "ul",
+    ];
+
+    enum Child<'a> {
+        Element(ElementRef<'a>),
+        Text(String),
+    }
+
+    fn write_element(element: ElementRef, verbatim: bool, out: &mut String) {
+        let name = element.value().name();
+        let verbatim =
+            verbatim || is_raw_text_element(name) || contains(PREFORMATTED_ELEMENTS, name);
+        let mut attributes = element.value().attrs().collect::<Vec<_>>();
+        attributes.sort();
+        out.push_str(&format!("<{name} {attributes:?}>"));
+
+        let mut children = Vec::new();
+        for child in element.children() {
+            match (child.value(), children.last_mut()) {
+                (Node::Text(text), Some(Child::Text(last))) => last.push_str(text),
+                (Node::Text(text), _) => children.push(Child::Text(text.to_string())),
+                (Node::Element(_), _) => {
+                    children.push(Child::Element(ElementRef::wrap(child).unwrap()))
+                }
+                _ => {}
+            }
+        }
+        let is_block = |i: Option<usize>| match i.and_then(|i| children.get(i)) {
+            Some(Child::Element(x)) => DISPLAY_BLOCK.contains(&x.value().name()),
+            Some(Child::Text(_)) => false,
+            None => DISPLAY_BLOCK.contains(&name),
+        };
+        for (i, child) in children.iter().enumerate() {
+            let text = match child {
+                Child::Element(child) => {
+                    write_element(*child, verbatim, out);
+                    continue;
+                }
+                Child::Text(text) if verbatim => text.clone(),
+                // Nothing in the head is shown
+                Child::Text(_) if name == "head" => continue,
+                Child::Text(text) => {
+                    let space = |x: &str| x.contains(|x: char| x.is_ascii_whitespace());
+                    let words = text.split_ascii_whitespace().collect::<Vec<_>>().join(" ");
+                    let before = space(&text[..text.len() - text.trim_start().len()])
+                        && !is_block(i.checked_sub(1));
+                    let after = space(&text[text.trim_end().len()..]) && !is_block(Some(i + 1));
+                    match (words.is_empty(), before, after) {
+                        (true, true, true) => " ".to_string(),
+                        (true, _, _) => continue,
+                        (false, before, after) => format!(
+                            "{}{words}{}",
+                            if before { " " } else { "" },
+                            if after { " " } else { "" }
+                        ),
+                    }
+                }
+            };
+            out.push_str(&format!("{text:?}"));
+        }
+        out.push_str(&format!("</{name}>"));
+    }
+
+    fn assert_same_dom(html: &str) {
+        let minified = minify(html);
+        assert_eq!(dom(&minified), dom(html), "minified to {minified}");
+    }
+
+    #[test]
+    fn leaves_out_optional_end_tags() {
+        assert_same_dom("<ul>\n  <li>a</li>\n  <li>b <b>c</b></li>\n</ul>");
+        assert_eq!(
+            minify("<ul><li>a</li><li>b</li></ul>"),
+            "<ul><li>a<li>b</ul>"
+        );
+        assert_same_dom("<dl><dt>a</dt><dd>b</dd><dt>c</dt></dl>");
+        assert_same_dom("<p>a</p>\n<div>b</div>\n<p>c</p>");
+        assert_same_dom("<!DOCTYPE html><p>a</p><table><tr><td>b</td></tr></table>");
+        assert_same_dom("<p>a</p><table><tr><td>b</td><td>c</td></tr><tr><td>d</td></tr></table>");
+        assert_same_dom("<div><p>a</p></div><a href=x><p>b</p></a>");
+    }
+
+    #[test]
+    fn keeps_preformatted_whitespace() {
+        assert_same_dom("<pre>\n  a\n\n    b  c\n</pre>");
+        assert_same_dom("<pre>\n\nx</pre><p> y </p>");
+        assert_same_dom("<pre><code>  fn main() {\n      <b>x</b>  y\n  }</code></pre>");
+        assert_same_dom("<textarea>\n  a\n   b </textarea>");
+        assert_same_dom("<script>if (a  <  b) {\n  c()\n}</script>");
+    }
+
+    #[test]
+    fn keeps_svg_intact() {
+        assert_same_dom(
+            "<p>a <svg viewBox=\"0 0 10 10\"><circle r=\"5\" cx=\"5\"/><path d=\"M0 0L10 10\" />\
+             <g><rect width=\"5\" height=\"5\"/></g></svg> b</p>",
+        );
+        assert_same_dom("<svg><circle r=\"5\"/><text x=\"1\">a</text></svg><p>b</p>");
+        assert_same_dom("<math><mi>x</mi><mspace width=\"1em\"/><mi>y</mi></math>");
+    }
+
+    #[test]
+    fn keeps_whitespace_between_inline_elements() {
+        assert_same_dom("<p><a href=\"/a\">a</a> <a href=\"/b\">b</a>\n<a href=\"/c\">c</a></p>");
+        assert_same_dom("<nav>\n  <a href=/a>a</a>\n  <a href=/b>b</a>\n</nav>");
+        assert_same_dom("<p>a<a href=/b>b </a>c <em> d</em><!-- e --> f</p>");
+        assert_eq!(
+            minify("<p><a href=\"/a\">a</a>\n  <a href=\"/b\">b</a></p>"),
+            "<p><a href=/a>a</a> <a href=/b>b</a></p>"
+        );
+    }
+
+    #[test]
+    fn keeps_whitespace_around_inline_scripts() {
+        assert_same_dom("<p>a <script>x()</script> b</p>");
+        assert_same_dom("<p>a <noscript>b</noscript> c</p>");
+        assert_same_dom("<p>a <style>b { }</style> c <link rel=x href=/y> d</p>");
+        assert_eq!(
+            minify("<p>a <script>x()</script> b</p>"),
+            "<p>a <script>x()</script> b</p>"
+        );
+        assert_eq!(
+            minify("<head>\n  <meta charset=utf-8>\n  <script>x()</script>\n</head>"),
+            "<head><meta charset=utf-8><script>x()</script></head>"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    html::{
+        check_raw_text, escape_attribute, escape_text, is_raw_text_element, Attribute, HtmlWriter,
+        Result,
+    },
+    tokenize::{
+        contains, is_block, tokenize, Tokens, BLOCK_ELEMENTS, PREFORMATTED_ELEMENTS, VOID_ELEMENTS,
+    },
+};
+
+const INDENT: &str = "  ";
+
+/// Writes HTML indented, one block element per line, for reading rather than serving
+///
+/// Line breaks only go next to block elements' tags, where whitespace does not show, so
+/// text and inline elements stay on the line they are on and the page looks the same.
+/// Whitespace in text is collapsed, except inside `pre`, `textarea`, `script` and
+/// `style`, which are written as is. Markup written with
+/// [`HtmlWriter::write_string_lit`] is reformatted the same way.
+pub struct PrettyHtml<T: Write> {
+    writer: T,
+    /// Open elements, innermost last
+    open: Vec<String>,
+    /// Whitespace held back until it is known whether a line break replaces it
+    space: bool,
+    /// Whether the last thing written ends its line, i.e. was a block element's tag
+    after_block: bool,
+    /// Whether the last thing written was the start tag of a block element, so an end
+    /// tag right after it can go on the same line
+    just_opened: bool,
+    /// Whether anything has been written yet
+    started: bool,
+}
+
+impl<T: Write> PrettyHtml<T> {
+    pub fn new(writer: T) -> Self {
+        PrettyHtml {
+            writer,
+            open: Vec::new(),
+            space: false,
+            after_block: true,
+            just_opened: false,
+            started: false,
+        }
+    }
+
+    /// Whether the content so far has to be written as is
+    fn in_verbatim(&self) -> bool {
+        self.open.last().is_some_and(|x| is_raw_text_element(x))
+            || self.open.iter().any(|x| contains(PREFORMATTED_ELEMENTS, x))
+    }
+
+    /// Starts a new line, indented by how many block elements are open
+    fn newline(&mut self) -> fmt::Result {
+        if self.started {
+            self.writer.write_char('\n')?;
+        }
+        let depth = self
+            .open
+            .iter()
+            .filter(|x| contains(BLOCK_ELEMENTS, x))
+            .count();
+        for _ in 0..depth {
+            self.writer.write_str(INDENT)?;
+        }
+        self.started = true;
+        self.space = false;
+        Ok(())
+    }
+
+    /// Puts what comes next on a new line or after a space, whichever is due
+    fn separate(&mut self, block: bool) -> fmt::Result {
+        if block || self.after_block {
+            self.newline()?;
+        } else if std::mem::take(&mut self.space) {
+            self.writer.write_char(' ')?;
+        }
+        self.started = true;
+        self.just_opened = false;
+        Ok(())
+    }
+
+    fn pop(&mut self, tag: &str) {
+        if let Some(i) = self.open.iter().rposition(|x| *x == tag) {
+            self.open.truncate(i);
+        }
+    }
+
+    /// Writes anything still held back, call once everything has been written
+    pub fn finish(mut self) -> Result<T> {
+        if self.started {
+            self.writer.write_char('\n')?;
+        }
+        Ok(self.writer)
+    }
+}
+
+impl<T: Write> Tokens for PrettyHtml<T> {
+    fn raw_text(&self) -> Option<String> {
+        self.open.last().filter(|x| is_raw_text_element(x)).cloned()
+    }
+
+    fn start_tag(
+        &mut self,
+        name: &str,
+        attributes: &[(&str, Option<&str>)],
+        self_closing: bool,
+    ) -> fmt::Result {
+        let tag = name.to_ascii_lowercase();
+        let block = is_block(&tag, &self.open);
+        if self.in_verbatim() {
+            self.just_opened = false;
+        } else {
+            self.separate(block)?;
+        }
+
+        write!(self.writer, "<{name}")?;
+        for (name, value) in attributes {
+            write!(self.writer, " {name}")?;
+            match value {
+                None => {}
+                Some(value) if !value.contains('"') => write!(self.writer, "=\"{value}\"")?,
+                Some(value) => write!(self.writer, "='{value}'")?,
+            }
+        }
+        if self_closing {
+            self.writer.write_str(" />")?;
+        } else {
+            self.writer.write_char('>')?;
+        }
+        let opened = !self_closing && !contains(VOID_ELEMENTS, &tag);
+        if opened {
+            self.open.push(tag);
+        }
+        self.after_block = block;
+        self.just_opened = block && opened;
+        Ok(())
+    }
+
+    fn end_tag(&mut self, name: &str) -> fmt::Result {
+        let tag = name.to_ascii_lowercase();
+        let block = is_block(&tag, &self.open);
+        if self.in_verbatim() || (block && self.just_opened) {
+            // Right after the content, so none of it is added to
+            self.pop(&tag);
+            self.space = false;
+        } else if block {
+            self.pop(&tag);
+            self.newline()?;
+        } else {
+            self.separate(false)?;
+            self.pop(&tag);
+        }
+        write!(self.writer, "</{name}>")?;
+        self.after_block = block;
+        self.just_opened = false;
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> fmt::Result {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.in_verbatim() {
+            self.writer.write_str(text)?;
+            self.after_block = false;
+            self.just_opened = false;
+            return Ok(());
+        }
+        if text.starts_with(|x: char| x.is_ascii_whitespace()) {
+            self.space = true;
+        }
+        for (i, word) in text.split_ascii_whitespace().enumerate() {
+            self.space |= i > 0;
+            self.separate(false)?;
+            self.writer.write_str(word)?;
+            self.after_block = false;
+        }
+        if text.ends_with(|x: char| x.is_ascii_whitespace()) {
+            self.space = true;
+        }
+        Ok(())
+    }
+
+    fn comment(&mut self, comment: &str) -> fmt::Result {
+        self.verbatim(comment)
+    }
+
+    fn verbatim(&mut self, markup: &str) -> fmt::Result {
+        // Like text, it only goes on a line of its own next to a block element's tag
+        if !self.in_verbatim() {
+            self.separate(false)?;
+        }
+        self.writer.write_str(markup)?;
+        self.after_block = false;
+        Ok(())
+    }
+}
+
+impl<T: Write> HtmlWriter for PrettyHtml<T> {
+    fn write_tag(&mut self, tag: &str, single: bool, attributes: Vec<Attribute>) -> Result {
+        let escaped = attributes
+            .iter()
+            .map(|(name, value)| (name, value.as_deref().map(escape_attribute)))
+            .collect::<Vec<_>>();
+        let attributes = escaped
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+            .collect::<Vec<_>>();
+        self.start_tag(tag, &attributes, single)?;
+        Ok(())
+    }
+
+    fn write_end_tag(&mut self, tag: &str) -> Result {
+        self.end_tag(tag)?;
+        Ok(())
+    }
+
+    fn write_string_lit(&mut self, lit: &str) -> Result {
+        tokenize(lit, self)?;
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> Result {
+        if let Some(tag) = self.raw_text() {
+            check_raw_text(text, &tag)?;
+            self.text(text)?;
+        } else {
+            self.text(&escape_text(text))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minify::tests::dom;
+
+    fn pretty(html: &str) -> String {
+        let mut writer = PrettyHtml::new(String::new());
+        writer.write_string_lit(html).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn assert_same_dom(html: &str) {
+        let pretty = pretty(html);
+        assert_eq!(dom(&pretty), dom(html), "pretty printed to {pretty}");
+    }
+
+    #[test]
+    fn keeps_the_dom() {
+        assert_same_dom(
+            "<!DOCTYPE html><html><head><title>a</title></head><body><p>b</p></body></html>",
+        );
+        assert_same_dom("<ul>\n  <li>a</li>\n  <li>b <b>c</b></li>\n</ul>");
+        assert_same_dom("<p>a</p><table><tr><td>b</td><td>c</td></tr></table><div>d</div>");
+        assert_same_dom("<p><a href=\"/a\">a</a> <a href=\"/b\">b</a>\n<a href=\"/c\">c</a></p>");
+        assert_same_dom("<p>a<a href=/b>b </a>c <em> d</em><!-- e --> f</p>");
+        assert_same_dom("<p>a<!-- x -->b</p>");
+        assert_same_dom("<p>Read <a href=/x>this</a> <noscript>b</noscript> now</p>");
+        assert_same_dom("<p>a <svg><circle r=\"5\"/><text x=\"1\">b</text></svg> c</p>");
+    }
+
+    #[test]
+    fn keeps_preformatted_whitespace() {
+        for html in [
+            "<pre>\n  a\n\n    b  c\n</pre>",
+            "<pre><code>  fn main() {\n      <b>x</b>  y\n  }</code></pre>",
+            "<textarea>\n  a\n   b </textarea>",
+            "<script>if (a  <  b) {\n  c()\n}</script>",
+            "<style>p  { color: red }\n</style>",
+        ] {
+            let wrapped = format!("<div><p>a</p>{html}</div>");
+            assert!(pretty(&wrapped).contains(html), "{}", pretty(&wrapped));
+            assert_same_dom(&wrapped);
+        }
+    }
+
+    #[test]
+    fn keeps_inline_runs_on_one_line() {
+        assert_eq!(
+            pretty("<p>a <b>b</b>\n <a href=\"/x\">c</a>, d</p>"),
+            "<p>\n  a <b>b</b> <a href=\"/x\">c</a>, d\n</p>\n"
+        );
+        assert_eq!(
+            pretty("<span>a</span><em>b</em>"),
+            "<span>a</span><em>b</em>\n"
+        );
+        assert_eq!(pretty("<p>a<!-- x -->b</p>"), "<p>\n  a<!-- x -->b\n</p>\n");
+        assert_eq!(
+            pretty("<p>Read <a>this</a> <script>x()</script> now</p>"),
+            "<p>\n  Read <a>this</a> <script>x()</script> now\n</p>\n"
+        );
+        assert_eq!(
+            pretty("<!DOCTYPE html><div><!-- x --></div>"),
+            "<!DOCTYPE html>\n<div>\n  <!-- x -->\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn indents_nested_blocks() {
+        assert_eq!(
+            pretty("<div><ul><li>a</li><li>b <b>c</b></li></ul><p></p></div>"),
+            "<div>\n  <ul>\n    <li>\n      a\n    </li>\n    <li>\n      b <b>c</b>\n    </li>\n  </ul>\n  <p></p>\n</div>\n"
+        );
+    }
+}
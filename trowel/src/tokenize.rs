@@ -0,0 +1,240 @@
+//! Just enough of an HTML tokenizer to rewrite markup written as string literals
+
+use std::fmt;
+
+/// Elements that never have content or an end tag
+pub(crate) const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements whitespace around which does not show
+///
+/// Not `script`, `style`, `noscript`, `link` or `meta`, which can sit in a line of text
+/// without breaking it. In the `head` nothing shows, see [`is_block`].
+pub(crate) const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Elements whose content is shown with its whitespace as is
+pub(crate) const PREFORMATTED_ELEMENTS: &[&str] = &["pre", "textarea"];
+
+pub(crate) fn contains(list: &[&str], tag: &str) -> bool {
+    list.contains(&tag)
+}
+
+/// Whether whitespace next to `tag` does not show, with `open` the elements it is in
+pub(crate) fn is_block(tag: &str, open: &[String]) -> bool {
+    contains(BLOCK_ELEMENTS, tag) || open.iter().any(|x| x == "head")
+}
+
+/// What [`tokenize`] hands the markup to, piece by piece
+pub(crate) trait Tokens {
+    /// The raw text element, e.g. `script`, the markup so far is inside of, if any
+    fn raw_text(&self) -> Option<String>;
+    /// `attributes` have their values as written, escaped already
+    fn start_tag(
+        &mut self,
+        name: &str,
+        attributes: &[(&str, Option<&str>)],
+        self_closing: bool,
+    ) -> fmt::Result;
+    fn end_tag(&mut self, name: &str) -> fmt::Result;
+    /// Escaped already, unless it is the content of a raw text element
+    fn text(&mut self, text: &str) -> fmt::Result;
+    /// A whole comment, `<!--` and `-->` included
+    fn comment(&mut self, comment: &str) -> fmt::Result;
+    /// Doctypes, processing instructions and the like
+    fn verbatim(&mut self, markup: &str) -> fmt::Result;
+}
+
+/// Splits `markup` into tags, text and comments, handing them to `tokens` in order
+pub(crate) fn tokenize(markup: &str, tokens: &mut impl Tokens) -> fmt::Result {
+    let mut rest = markup;
+    while !rest.is_empty() {
+        if let Some(tag) = tokens.raw_text() {
+            let end = find_end_tag(rest, &tag).unwrap_or(rest.len());
+            tokens.text(&rest[..end])?;
+            rest = &rest[end..];
+            if rest.is_empty() {
+                break;
+            }
+        }
+        let Some(start) = rest.find('<') else {
+            tokens.text(rest)?;
+            break;
+        };
+        tokens.text(&rest[..start])?;
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").map_or(comment.len(), |x| x + 3);
+            tokens.comment(&rest[..end + 4])?;
+            rest = &comment[end..];
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            let end = rest.find('>').map_or(rest.len(), |x| x + 1);
+            tokens.verbatim(&rest[..end])?;
+            rest = &rest[end..];
+        } else if let Some((tag, len)) = parse_tag(rest) {
+            match tag {
+                Tag::Start {
+                    name,
+                    attributes,
+                    self_closing,
+                } => tokens.start_tag(name, &attributes, self_closing)?,
+                Tag::End(name) => tokens.end_tag(name)?,
+            }
+            rest = &rest[len..];
+        } else {
+            tokens.text("<")?;
+            rest = &rest[1..];
+        }
+    }
+    Ok(())
+}
+
+/// A tag as it is written, names in their original case and attribute values still
+/// escaped
+pub enum Tag<'a> {
+    Start {
+        name: &'a str,
+        /// In source order, without a value for ones like `<input disabled>`
+        attributes: Vec<(&'a str, Option<&'a str>)>,
+        self_closing: bool,
+    },
+    End(&'a str),
+}
+
+/// The start or end tag `input` begins with, and how long it is
+pub fn parse_tag(input: &str) -> Option<(Tag<'_>, usize)> {
+    let bytes = input.as_bytes();
+    let end_tag = bytes.get(1) == Some(&b'/');
+    let name_start = if end_tag { 2 } else { 1 };
+    if !bytes.get(name_start)?.is_ascii_alphabetic() {
+        return None;
+    }
+    let is_name_end = |x: u8| x.is_ascii_whitespace() || matches!(x, b'>' | b'/' | b'=');
+    let mut i = name_start;
+    while i < bytes.len() && !is_name_end(bytes[i]) {
+        i += 1;
+    }
+    let name = &input[name_start..i];
+    if end_tag {
+        let len = input[i..].find('>')? + i + 1;
+        return Some((Tag::End(name), len));
+    }
+
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => break,
+            b'/' => {
+                self_closing = bytes.get(i + 1) == Some(&b'>');
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let start = i;
+        while i < bytes.len() && !is_name_end(bytes[i]) {
+            i += 1;
+        }
+        if start == i {
+            // Not something an attribute name can start with, skip it
+            i += 1;
+            continue;
+        }
+        let attribute = &input[start..i];
+        let mut value = None;
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let end = input[i + 1..].find(quote as char)? + i + 1;
+                    value = Some(&input[i + 1..end]);
+                    i = end + 1;
+                }
+                _ => {
+                    let start = i;
+                    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+                    value = Some(&input[start..i]);
+                }
+            }
+        }
+        attributes.push((attribute, value));
+    }
+    let tag = Tag::Start {
+        name,
+        attributes,
+        self_closing,
+    };
+    Some((tag, i + 1))
+}
+
+/// Where the end tag of `tag` starts in `input`
+fn find_end_tag(input: &str, tag: &str) -> Option<usize> {
+    let lower = input.to_ascii_lowercase();
+    let needle = format!("</{tag}");
+    let mut from = 0;
+    while let Some(i) = lower[from..].find(&needle) {
+        let i = from + i;
+        let after = lower.as_bytes().get(i + needle.len());
+        if after.is_none_or(|x| x.is_ascii_whitespace() || matches!(x, b'>' | b'/')) {
+            return Some(i);
+        }
+        from = i + needle.len();
+    }
+    None
+}
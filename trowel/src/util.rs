@@ -1,27 +1,92 @@
 use trowel::trowel_html;
 
-use crate::{html::Attribute, HtmlWriter, ToHtml};
+use crate::{
+    html::{Attribute, Error, Result},
+    HtmlWriter, ToHtml,
+};
 
 #[allow(non_snake_case)]
-pub fn Doctype(attrs: Vec<Attribute>, _children: impl Fn(&mut dyn HtmlWriter)) -> impl ToHtml {
-    assert!(attrs.is_empty());
-    trowel_html!(r#"<!DOCTYPE html>"#)
+pub fn Doctype(
+    attrs: Vec<Attribute>,
+    _children: impl Fn(&mut dyn HtmlWriter) -> Result,
+) -> Result<impl ToHtml> {
+    if !attrs.is_empty() {
+        return Err(Error::new(format!("unexpected attributes {attrs:?}")));
+    }
+    Ok(trowel_html!(r#"<!DOCTYPE html>"#))
 }
 
+/// Charset, viewport and the default stylesheets,
+/// pass `no_stylesheets` to leave the stylesheets out
 #[allow(non_snake_case)]
-pub fn HeadDefault(attrs: Vec<Attribute>, _children: impl Fn(&mut dyn HtmlWriter)) -> impl ToHtml {
-    assert!(attrs.is_empty());
-    trowel_html! {
+pub fn HeadDefault(
+    attrs: Vec<Attribute>,
+    _children: impl Fn(&mut dyn HtmlWriter) -> Result,
+) -> Result<impl ToHtml> {
+    let stylesheets = match attrs.as_slice() {
+        [] => true,
+        [(name, None)] if name == "no_stylesheets" => false,
+        _ => return Err(Error::new(format!("unexpected attributes {attrs:?}"))),
+    };
+    Ok(trowel_html! { move
         r#"<meta charset="utf-8" />"#,
-        r#"<link rel="stylesheet" href="https://unpkg.com/normalize.css@7.0.0/normalize.css" type="text/css" />"#,
-        r#"<link rel="stylesheet" href="https://unpkg.com/sakura.css@1.5.0/css/sakura-pink.css" type="text/css" />"#,
+        {stylesheets.then_some(trowel_html! {
+            r#"<link rel="stylesheet" href="https://unpkg.com/normalize.css@7.0.0/normalize.css" type="text/css" />"#,
+            r#"<link rel="stylesheet" href="https://unpkg.com/sakura.css@1.5.0/css/sakura-pink.css" type="text/css" />"#,
+        })},
         r#"<meta name="viewport" content="width=device-width, initial-scale=1" />"#
-    }
+    })
 }
 
-pub fn html_to_string(x: impl FnOnce(&mut dyn HtmlWriter)) -> String {
+pub fn html_to_string(x: impl FnOnce(&mut dyn HtmlWriter) -> Result) -> Result<String> {
     let mut buf = String::new();
     let mut writer = crate::html::WriteHtml::new(&mut buf);
-    x(&mut writer);
-    buf
+    x(&mut writer)?;
+    Ok(buf)
+}
+
+pub fn xml_to_string(x: impl FnOnce(&mut dyn HtmlWriter) -> Result) -> Result<String> {
+    let mut buf = String::new();
+    let mut writer = crate::html::WriteHtml::xml(&mut buf);
+    x(&mut writer)?;
+    Ok(buf)
+}
+
+/// Like [`html_to_string`], but streamed to `writer` as it is written, e.g. straight
+/// to a file
+pub fn write_html<W: std::io::Write>(
+    writer: W,
+    x: impl FnOnce(&mut dyn HtmlWriter) -> Result,
+) -> Result<W> {
+    let mut writer = crate::html::WriteHtml::new(crate::html::IoWriter::new(writer));
+    let result = x(&mut writer);
+    let writer = writer.into_inner().finish()?;
+    result?;
+    Ok(writer)
+}
+
+/// Like [`html_to_string`], but minified with [`crate::minify::MinifyHtml`]
+pub fn html_to_minified_string(x: impl FnOnce(&mut dyn HtmlWriter) -> Result) -> Result<String> {
+    let mut writer = crate::minify::MinifyHtml::new(String::new());
+    x(&mut writer)?;
+    writer.finish()
+}
+
+/// Minifies already rendered `html` with [`crate::minify::MinifyHtml`]
+pub fn minify_html(html: &str) -> String {
+    html_to_minified_string(|writer| writer.write_string_lit(html))
+        .expect("writing markup to a String can not fail")
+}
+
+/// Like [`html_to_string`], but indented with [`crate::pretty::PrettyHtml`]
+pub fn html_to_pretty_string(x: impl FnOnce(&mut dyn HtmlWriter) -> Result) -> Result<String> {
+    let mut writer = crate::pretty::PrettyHtml::new(String::new());
+    x(&mut writer)?;
+    writer.finish()
+}
+
+/// Indents already rendered `html` with [`crate::pretty::PrettyHtml`]
+pub fn pretty_html(html: &str) -> String {
+    html_to_pretty_string(|writer| writer.write_string_lit(html))
+        .expect("writing markup to a String can not fail")
 }
@@ -1,6 +1,8 @@
 use quote::{quote, quote_spanned};
 use syn::{
-    braced, bracketed, parenthesized,
+    braced, bracketed,
+    ext::IdentExt,
+    parenthesized,
     parse::{self, Parse},
     punctuated::Punctuated,
     spanned::Spanned,
@@ -16,7 +18,8 @@ enum HtmlAttribute {
 
 impl Parse for HtmlAttribute {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let ident = input.parse::<Ident>()?;
+        // Allow keywords such as `type` as attribute names
+        let ident = input.call(Ident::parse_any)?;
         if input.peek(syn::token::Bracket) {
             let if_expr;
             bracketed!(if_expr in input);
@@ -85,22 +88,19 @@ impl Parse for HtmlAstElem {
             }
         }
         let name = input.parse::<Path>()?;
-        let attributes;
+        let attributes = if input.peek(syn::token::Paren) {
+            let attributes;
+            parenthesized!(attributes in input);
+            Punctuated::<HtmlAttribute, Token![,]>::parse_terminated(&attributes)?
+        } else {
+            Punctuated::new()
+        };
         let children;
         if !is_single {
-            attributes = if input.peek(syn::token::Paren) {
-                let attributes;
-                parenthesized!(attributes in input);
-                Punctuated::<HtmlAttribute, Token![,]>::parse_terminated(&attributes)?
-            } else {
-                Punctuated::new()
-            };
-
             let p_children;
             braced!(p_children in input);
             children = Punctuated::<HtmlAstElem, Token![,]>::parse_terminated(&p_children)?;
         } else {
-            attributes = Punctuated::new();
             children = Punctuated::new();
         }
 
@@ -142,14 +142,16 @@ pub fn trowel_html(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         fn parse(input: parse::ParseStream) -> parse::Result<Self> {
             let move_token = input.parse::<Option<Token![move]>>()?;
             let tree = Punctuated::<HtmlAstElem, Token![,]>::parse_terminated(input)
-                .map(|x| x.into_iter().map(generate_html))?.collect();
+                .map(|x| x.into_iter().map(generate_html))?
+                .collect();
             Ok(HtmlMacroInput { move_token, tree })
         }
     }
     let HtmlMacroInput { move_token, tree } = syn::parse::<HtmlMacroInput>(input).unwrap();
     quote! {
-        #move_token |writer: &mut dyn (::trowel::HtmlWriter)| {
-            #(#tree);*
+        #move_token |writer: &mut dyn (::trowel::HtmlWriter)| -> ::trowel::html::Result {
+            #(#tree)*
+            Ok(())
         }
     }
     .into()
@@ -159,7 +161,7 @@ fn html_expr(expr: Expr) -> proc_macro2::TokenStream {
     quote_spanned! {
         expr.span() =>
         {
-            (::trowel::ToHtml::to_html(#expr, writer));
+            ::trowel::ToHtml::to_html(#expr, writer)?;
         }
     }
 }
@@ -209,7 +211,7 @@ fn generate_attributes_list(
 fn generate_html(elem: HtmlAstElem) -> proc_macro2::TokenStream {
     let inner = match elem {
         HtmlAstElem::Text(str) => {
-            quote! {writer.write_string_lit(#str);}
+            quote! {writer.write_string_lit(#str)?;}
         }
         HtmlAstElem::Fragment {
             name,
@@ -218,13 +220,16 @@ fn generate_html(elem: HtmlAstElem) -> proc_macro2::TokenStream {
         } => {
             let attributes_list = generate_attributes_list(attributes);
             let children = children.into_iter().map(generate_html).collect::<Vec<_>>();
+            // Errors are put down to the component, e.g. `BlogPageBase`, not its whole path
+            let component = name.segments.last().map(|x| x.ident.to_string());
             quote! {
                 {
-                    (::trowel::ToHtml::to_html(#name (
-                        {#attributes_list}, |writer: &mut dyn (::trowel::HtmlWriter)| {
-                            #(#children);*
+                    ::trowel::ToHtml::to_html(#name (
+                        {#attributes_list}, |writer: &mut dyn (::trowel::HtmlWriter)| -> ::trowel::html::Result {
+                            #(#children)*
+                            Ok(())
                         }
-                    ), writer));
+                    ), writer).map_err(|err| err.within(#component))?;
                 }
             }
         }
@@ -237,14 +242,14 @@ fn generate_html(elem: HtmlAstElem) -> proc_macro2::TokenStream {
             let attributes_list = generate_attributes_list(attributes);
             let children = children.into_iter().map(generate_html).collect::<Vec<_>>();
             quote! {
-
-                    writer.write_tag(stringify!(#name), #single_tag, {
-                        #attributes_list
-                    });
+                ::trowel::html::write_element(writer, stringify!(#name), #single_tag, {
+                    #attributes_list
+                }, |writer: &mut dyn (::trowel::HtmlWriter)| -> ::trowel::html::Result {
                     #(
                         #children
-                    );*
-                    writer.write_end_tag(stringify!(#name));
+                    )*
+                    Ok(())
+                })?;
             }
         }
         HtmlAstElem::Expression(expr) => html_expr(expr),